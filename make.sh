@@ -27,10 +27,31 @@ EOF
 
 dev() {
     livereload &
-    find . -name '*.rs' -or -name '*.toml' | entr -c -r env ROCKET_ENV=stage cargo run
+    # staging wants a cookie key; one per run is fine here
+    key="${ROCKET_SECRET_KEY:-$(openssl rand -base64 32)}"
+    find . -name '*.rs' -or -name '*.toml' | entr -c -r env ROCKET_ENV=stage ROCKET_SECRET_KEY="$key" cargo run
+}
+
+# Fetches any of the third-party CSS and JS missing from static/vendor, where
+# they are committed (delete one and commit the new copy to upgrade it), then
+# writes .gz and .br copies of the static files.
+assets() {
+    mkdir -p "$SCRIPTDIR/static/vendor"
+    fetch() {
+        [ -s "$SCRIPTDIR/static/vendor/$1" ] || curl -fsSL -o "$SCRIPTDIR/static/vendor/$1" "$2"
+    }
+    fetch tailwind.min.css https://unpkg.com/tailwindcss@1.9.6/dist/tailwind.min.css
+    fetch highlight.min.js https://cdn.jsdelivr.net/gh/highlightjs/cdn-release@10.1.2/build/highlight.min.js
+    fetch highlight.min.css https://cdn.jsdelivr.net/gh/highlightjs/cdn-release@10.1.2/build/styles/default.min.css
+    find "$SCRIPTDIR/static" -type f \( -name '*.js' -o -name '*.css' -o -name '*.svg' -o -name '*.ico' \) |
+    while read -r file; do
+        gzip -k -f -9 -n "$file"
+        if command -v brotli >/dev/null; then brotli -k -f "$file"; fi
+    done
 }
 
 deploy() {
+    assets
     cargo build --release
 }
 
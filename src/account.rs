@@ -0,0 +1,410 @@
+//! Local user accounts. Users log in with a password and are then known by
+//! a private (encrypted) `session` cookie holding a random session ID.
+//! Sessions are kept as `upload/.sessions/<SHA-256 of the ID>` and end on
+//! logout or `SESSION_LIFETIME` after logging in. Outside development the
+//! cookie key must come from `ROCKET_SECRET_KEY`, never `Rocket.toml`.
+//! Pastes submitted through the paste form while logged in are attributed to
+//! the user and listed on `/my`, where they can be searched and deleted.
+//!
+//! Accounts are stored as `upload/.users/<name>` in the `key=value` format
+//! of the `.meta` files, with the IDs of the user's pastes in
+//! `upload/.users/<name>.pastes`. `admin=true` marks the accounts allowed
+//! into `/admin`; it is only set by `pastebin make-admin <name>`, on an
+//! account that already exists. `registration = false` in `Rocket.toml`
+//! closes registration. Failed logins are limited per name and address,
+//! registrations per address, and the registration form asks for the same
+//! proof of work as the paste form.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use maud::Markup;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{Cookie, Cookies};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{Debug, Redirect};
+use rocket::{Outcome, Route, State};
+use sha2::{Digest, Sha256};
+
+use crate::csrf::{self, CsrfForm, CsrfToken};
+use crate::events::{EventHub, PasteEvent};
+use crate::meta::{self, unix_now, PasteMeta};
+use crate::password;
+use crate::paste_id::PasteID;
+use crate::pow::{Challenges, ProofOfWork};
+use crate::rate_limit::{ClientIp, RateLimiter};
+use crate::security_headers::Nonce;
+use crate::{login_page, my_pastes_page, register_page, ServerAcceptLangauge};
+
+const USERS_DIR: &str = "upload/.users";
+const SESSIONS_DIR: &str = "upload/.sessions";
+const SESSION_COOKIE: &str = "session";
+const SESSION_ID_LENGTH: usize = 32;
+const SESSION_LIFETIME: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Only this much of each paste is searched.
+const SEARCH_LIMIT: u64 = 1 << 20;
+
+fn user_path(name: &str) -> String {
+    format!("{dir}/{name}", dir = USERS_DIR, name = name)
+}
+
+fn pastes_path(name: &str) -> String {
+    format!("{dir}/{name}.pastes", dir = USERS_DIR, name = name)
+}
+
+/// Lowercase letters, digits, `-` and `_`; the name is also a file name.
+fn valid_name(name: &str) -> bool {
+    (3..=32).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn field(name: &str, key: &str) -> Option<String> {
+    let text = fs::read_to_string(user_path(name)).ok()?;
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(field, _)| *field == key)
+        .map(|(_, value)| value.to_string())
+}
+
+fn password_hash(name: &str) -> Option<String> {
+    field(name, "password_hash")
+}
+
+pub fn is_admin(name: &str) -> bool {
+    field(name, "admin").as_deref() == Some("true")
+}
+
+/// Marks `name` as an admin or not; used by the server's `make-admin`
+/// command.
+pub fn set_admin(name: &str, admin: bool) -> io::Result<()> {
+    if !valid_name(name) || !Path::new(&user_path(name)).exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("there is no account named {}", name)));
+    }
+    let mut text: String = fs::read_to_string(user_path(name))?
+        .lines()
+        .filter(|line| !line.starts_with("admin="))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    if admin {
+        text.push_str("admin=true\n");
+    }
+    fs::write(user_path(name), text)
+}
+
+/// Only a hash of the session ID is stored, so the files alone let no one in.
+fn session_path(id: &str) -> String {
+    let hash: String = Sha256::digest(id.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{dir}/{hash}", dir = SESSIONS_DIR, hash = hash)
+}
+
+/// The `user` and `expires` of a session file.
+fn read_session(path: impl AsRef<Path>) -> Option<(Option<String>, Option<u64>)> {
+    let text = fs::read_to_string(path).ok()?;
+    let (mut user, mut expires) = (None, None);
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "user" => user = Some(value.to_string()),
+            "expires" => expires = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((user, expires))
+}
+
+/// The user of session `id`, unless it has ended.
+fn session_user(id: &str) -> Option<String> {
+    match read_session(session_path(id))? {
+        (Some(user), Some(expires)) if expires > unix_now() => Some(user),
+        _ => None,
+    }
+}
+
+/// Removes ended sessions; done whenever a session is created.
+fn prune_sessions() {
+    let now = unix_now();
+    for entry in fs::read_dir(SESSIONS_DIR).into_iter().flatten().flatten() {
+        // Files being written have no `expires` yet.
+        if let Some((_, Some(expires))) = read_session(entry.path()) {
+            if expires <= now {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Stores a new session for `name`, returning its ID.
+fn create_session(name: &str) -> io::Result<String> {
+    fs::create_dir_all(SESSIONS_DIR)?;
+    prune_sessions();
+    let id = PasteID::new(SESSION_ID_LENGTH).to_string();
+    let mut file = OpenOptions::new().write(true).create_new(true).open(session_path(&id))?;
+    write!(file, "user={}\nexpires={}\n", name, unix_now() + SESSION_LIFETIME.as_secs())?;
+    Ok(id)
+}
+
+/// Why registering or logging in failed; the variant names the message
+/// shown on the form.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccountError {
+    InvalidName,
+    ShortPassword,
+    NameTaken,
+    WrongPassword,
+    TooManyAttempts,
+    RegistrationClosed,
+}
+
+impl AccountError {
+    pub fn text_key(self) -> &'static str {
+        match self {
+            AccountError::InvalidName => "account-invalid-name",
+            AccountError::ShortPassword => "account-short-password",
+            AccountError::NameTaken => "account-name-taken",
+            AccountError::WrongPassword => "account-wrong-password",
+            AccountError::TooManyAttempts => "account-too-many-attempts",
+            AccountError::RegistrationClosed => "account-registration-closed",
+        }
+    }
+}
+
+pub fn register(name: &str, password: &str) -> io::Result<Result<(), AccountError>> {
+    if !valid_name(name) {
+        return Ok(Err(AccountError::InvalidName));
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Ok(Err(AccountError::ShortPassword));
+    }
+    let hash = password::hash(password).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::create_dir_all(USERS_DIR)?;
+    // `create_new` makes taking the name atomic.
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(user_path(name)) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(Err(AccountError::NameTaken)),
+        Err(e) => return Err(e),
+    };
+    write!(file, "created={}\npassword_hash={}\n", unix_now(), hash)?;
+    Ok(Ok(()))
+}
+
+/// Remembers that `id` was uploaded by `name`.
+pub fn record_paste(name: &str, id: &str) -> io::Result<()> {
+    let mut pastes = OpenOptions::new().create(true).append(true).open(pastes_path(name))?;
+    writeln!(pastes, "{}", id)
+}
+
+fn matches(id: &str, meta: &PasteMeta, query: &str) -> bool {
+    let query = query.to_lowercase();
+    let found = |text: &str| text.to_lowercase().contains(&query);
+    if found(id) || meta.title.as_deref().map_or(false, found) || meta.filename.as_deref().map_or(false, found) {
+        return true;
+    }
+    // Encrypted pastes are only ciphertext here.
+    let mut text = Vec::new();
+    !meta.encrypted
+        && File::open(format!("upload/{id}", id = id))
+            .and_then(|file| file.take(SEARCH_LIMIT).read_to_end(&mut text))
+            .is_ok()
+        && found(&String::from_utf8_lossy(&text))
+}
+
+/// The live pastes of `name`, newest first, optionally only those whose
+/// ID, title, file name or text contains `query`.
+pub fn pastes(name: &str, query: Option<&str>) -> Vec<(String, PasteMeta)> {
+    let ids = fs::read_to_string(pastes_path(name)).unwrap_or_default();
+    ids.lines().rev()
+        .filter(|id| meta::exists(id))
+        .map(|id| (id.to_string(), PasteMeta::load(id)))
+        .filter(|(_, meta)| meta.user.as_deref() == Some(name))
+        .filter(|(id, meta)| query.map_or(true, |query| matches(id, meta, query)))
+        .collect()
+}
+
+/// The logged-in user. Forwards when there is no session.
+pub struct User {
+    pub name: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let session = request.cookies().get_private(SESSION_COOKIE);
+        match session.and_then(|cookie| session_user(cookie.value())) {
+            // Deleted accounts lose their sessions.
+            Some(name) if password_hash(&name).is_some() => Outcome::Success(User { name }),
+            _ => Outcome::Forward(()),
+        }
+    }
+}
+
+/// `registration` from `Rocket.toml`, failed logins per account name and
+/// address, and registrations per address.
+pub struct Accounts {
+    registration: bool,
+    logins: RateLimiter,
+    registrations: RateLimiter,
+}
+
+impl Accounts {
+    fn log_in(&self, name: &str, password: &str, client: &ClientIp) -> Result<(), AccountError> {
+        let key = format!("{} {}", name, client.key());
+        self.logins.peek(&key).map_err(|_| AccountError::TooManyAttempts)?;
+        match password_hash(name).map_or(false, |hash| password::verify(&hash, password)) {
+            true => Ok(()),
+            false => {
+                let _ = self.logins.check(&key);
+                Err(AccountError::WrongPassword)
+            }
+        }
+    }
+}
+
+#[derive(FromForm)]
+struct Credentials {
+    name: String,
+    password: String,
+}
+
+#[derive(Responder)]
+enum Page {
+    Page(Markup),
+    #[response(status = 401)]
+    Unauthorized(Markup),
+    #[response(status = 403)]
+    Forbidden(Markup),
+    #[response(status = 429)]
+    TooManyRequests(Markup),
+    Redirect(Redirect),
+    Io(Debug<io::Error>),
+}
+
+/// Also replaces the CSRF token, so that one seen before logging in is no
+/// good afterwards.
+fn start_session(cookies: &mut Cookies<'_>, name: &str) -> io::Result<()> {
+    cookies.add_private(Cookie::new(SESSION_COOKIE, create_session(name)?));
+    csrf::rotate(cookies);
+    Ok(())
+}
+
+// The `Cookies` guards come last: while one is alive, other guards see no
+// cookies.
+
+#[get("/login")]
+fn login_form(csrf: CsrfToken, lang: ServerAcceptLangauge) -> Markup {
+    login_page(None, &csrf, lang)
+}
+
+#[post("/login", data = "<form>")]
+fn login(
+    csrf: CsrfToken,
+    form: CsrfForm<Credentials>,
+    client: ClientIp,
+    accounts: State<Accounts>,
+    lang: ServerAcceptLangauge,
+    mut cookies: Cookies<'_>,
+) -> Page {
+    let name = form.name.trim().to_lowercase();
+    match accounts.log_in(&name, &form.password, &client) {
+        Ok(()) => match start_session(&mut cookies, &name) {
+            Ok(()) => Page::Redirect(Redirect::to("/my")),
+            Err(e) => Page::Io(Debug(e)),
+        },
+        Err(error) => Page::Unauthorized(login_page(Some(error), &csrf, lang)),
+    }
+}
+
+#[get("/register")]
+fn register_form(
+    accounts: State<Accounts>,
+    challenges: State<Challenges>,
+    csrf: CsrfToken,
+    nonce: Nonce,
+    lang: ServerAcceptLangauge,
+) -> Markup {
+    let error = Some(AccountError::RegistrationClosed).filter(|_| !accounts.registration);
+    register_page(error, &challenges.issue(), &csrf, &nonce, lang)
+}
+
+#[post("/register", data = "<form>")]
+fn register_account(
+    _pow: ProofOfWork,
+    csrf: CsrfToken,
+    nonce: Nonce,
+    form: CsrfForm<Credentials>,
+    client: ClientIp,
+    accounts: State<Accounts>,
+    challenges: State<Challenges>,
+    lang: ServerAcceptLangauge,
+    mut cookies: Cookies<'_>,
+) -> Page {
+    let page = |error| register_page(Some(error), &challenges.issue(), &csrf, &nonce, lang);
+    if !accounts.registration {
+        return Page::Forbidden(page(AccountError::RegistrationClosed));
+    }
+    if accounts.registrations.check(&client.key()).is_err() {
+        return Page::TooManyRequests(page(AccountError::TooManyAttempts));
+    }
+    let name = form.name.trim().to_lowercase();
+    match register(&name, &form.password) {
+        Ok(Ok(())) => match start_session(&mut cookies, &name) {
+            Ok(()) => Page::Redirect(Redirect::to("/my")),
+            Err(e) => Page::Io(Debug(e)),
+        },
+        Ok(Err(error)) => Page::Page(page(error)),
+        Err(e) => Page::Io(Debug(e)),
+    }
+}
+
+#[post("/logout", data = "<_csrf>")]
+fn logout(_csrf: CsrfForm, mut cookies: Cookies<'_>) -> Redirect {
+    if let Some(session) = cookies.get_private(SESSION_COOKIE) {
+        let _ = fs::remove_file(session_path(session.value()));
+    }
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    csrf::rotate(&mut cookies);
+    Redirect::to("/")
+}
+
+#[get("/my?<q>")]
+fn my_pastes(user: User, q: Option<String>, csrf: CsrfToken, lang: ServerAcceptLangauge) -> Markup {
+    let query = q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    my_pastes_page(&user.name, &pastes(&user.name, query), query, &csrf, lang)
+}
+
+#[get("/my", rank = 2)]
+fn my_pastes_login() -> Redirect {
+    Redirect::to("/login")
+}
+
+#[post("/my/<id>/delete", data = "<_csrf>")]
+fn delete(user: User, id: PasteID<'_>, hub: State<EventHub>, _csrf: CsrfForm) -> Option<Redirect> {
+    let id = id.to_string();
+    let meta = PasteMeta::load_live(&id)?;
+    if meta.user.as_deref() != Some(user.name.as_str()) {
+        return None;
+    }
+    meta::delete(&id);
+    hub.publish(&id, PasteEvent::Deleted);
+    Some(Redirect::to("/my"))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![login_form, login, register_form, register_account, logout, my_pastes, my_pastes_login, delete]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("accounts", |rocket| {
+        // A key in a checked-in file seals nobody's cookies.
+        if !rocket.config().environment.is_dev() && env::var("ROCKET_SECRET_KEY").is_err() {
+            eprintln!("set ROCKET_SECRET_KEY outside development, e.g. to the output of `openssl rand -base64 32`");
+            return Err(rocket);
+        }
+        let registration = rocket.config().get_bool("registration").unwrap_or(true);
+        let logins = RateLimiter::new(5, Duration::from_secs(60));
+        let registrations = RateLimiter::new(5, Duration::from_secs(60 * 60));
+        Ok(rocket.manage(Accounts { registration, logins, registrations }))
+    })
+}
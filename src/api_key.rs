@@ -0,0 +1,329 @@
+//! Personal API keys, sent as `Authorization: Bearer pb_<id>_<secret>`.
+//! Pastes uploaded with a key are attributed to it, and a key with the
+//! `delete` scope can delete them without their owner tokens.
+//!
+//! Keys are stored as `upload/.keys/<id>` in the `key=value` format of the
+//! `.meta` files, with only a SHA-256 hash of the secret. Admin keys mint and
+//! revoke keys under `/api/keys`; the first one comes from running
+//! `pastebin mint-admin-key` on the server. Anonymous uploads can be turned
+//! off with `anonymous_uploads = false`.
+
+use std::fs;
+use std::io;
+
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{status, Debug};
+use rocket::{Outcome, Route, State};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::account::User;
+use crate::meta::{tokens_match, unix_now, PasteMeta};
+use crate::moderation::{hash_ip, is_banned, BanKind};
+use crate::options::UploadOptions;
+use crate::paste_id::PasteID;
+use crate::rate_limit::ClientIp;
+
+const KEYS_DIR: &str = "upload/.keys";
+const ID_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Create pastes.
+    Upload,
+    /// Delete pastes uploaded with the key.
+    Delete,
+    /// Manage keys and delete any paste.
+    Admin,
+}
+
+impl Scope {
+    fn parse(value: &str) -> Option<Scope> {
+        match value {
+            "upload" => Some(Scope::Upload),
+            "delete" => Some(Scope::Delete),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Scope::Upload => "upload",
+            Scope::Delete => "delete",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created: u64,
+    #[serde(skip)]
+    secret_hash: String,
+}
+
+fn key_path(id: &str) -> String {
+    format!("{dir}/{id}", dir = KEYS_DIR, id = id)
+}
+
+/// The SHA-256 of a secret in hex, which is all that is stored of it.
+pub fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl ApiKey {
+    fn load(id: &str) -> Option<ApiKey> {
+        let text = fs::read_to_string(key_path(id)).ok()?;
+        let mut key = ApiKey { id: id.to_string(), name: String::new(), scopes: Vec::new(), created: 0, secret_hash: String::new() };
+        for (name, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            match name {
+                "name" => key.name = value.to_string(),
+                "scopes" => key.scopes = value.split(',').filter_map(Scope::parse).collect(),
+                "created" => key.created = value.parse().unwrap_or(0),
+                "secret_hash" => key.secret_hash = value.to_string(),
+                _ => {}
+            }
+        }
+        Some(key).filter(|key| !key.secret_hash.is_empty())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let scopes: Vec<&str> = self.scopes.iter().map(|scope| scope.name()).collect();
+        fs::create_dir_all(KEYS_DIR)?;
+        fs::write(
+            key_path(&self.id),
+            format!(
+                "name={}\nscopes={}\ncreated={}\nsecret_hash={}\n",
+                self.name, scopes.join(","), self.created, self.secret_hash
+            ),
+        )
+    }
+
+    /// Looks up the key of a `pb_<id>_<secret>` token.
+    fn find(token: &str) -> Option<ApiKey> {
+        let (id, secret) = token.strip_prefix("pb_")?.split_once('_')?;
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        ApiKey::load(id).filter(|key| tokens_match(&key.secret_hash, &hash_secret(secret)))
+    }
+
+    pub fn has(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Whether the key may delete the paste `id`: admin keys any paste,
+    /// `delete` keys the ones uploaded with them.
+    pub fn may_delete(&self, meta: &PasteMeta) -> bool {
+        self.has(Scope::Admin) || (self.has(Scope::Delete) && meta.api_key.as_ref() == Some(&self.id))
+    }
+}
+
+/// Creates a key, returning it with its token. The token is not stored and
+/// can't be shown again.
+pub fn mint(name: &str, scopes: &[Scope]) -> io::Result<(ApiKey, String)> {
+    let key = ApiKey {
+        id: PasteID::new(ID_LENGTH).to_string(),
+        name: name.to_string(),
+        scopes: scopes.to_vec(),
+        created: unix_now(),
+        secret_hash: String::new(),
+    };
+    let secret = PasteID::new(SECRET_LENGTH).to_string();
+    let key = ApiKey { secret_hash: hash_secret(&secret), ..key };
+    key.save()?;
+    let token = format!("pb_{}_{}", key.id, secret);
+    Ok((key, token))
+}
+
+pub fn list() -> Vec<ApiKey> {
+    let mut keys: Vec<ApiKey> = fs::read_dir(KEYS_DIR)
+        .map(|entries| {
+            entries.filter_map(Result::ok)
+                .filter_map(|entry| ApiKey::load(&entry.file_name().to_string_lossy()))
+                .collect()
+        })
+        .unwrap_or_default();
+    keys.sort_by_key(|key| key.created);
+    keys
+}
+
+fn bearer<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request.headers().get_one("Authorization")?.strip_prefix("Bearer ").map(str::trim)
+}
+
+/// The key of the request, looked up once per request.
+fn request_key(request: &Request<'_>) -> Option<ApiKey> {
+    request.local_cache(|| bearer(request).and_then(ApiKey::find)).clone()
+}
+
+/// Fails with 401 unless the request has a valid key.
+impl<'a, 'r> FromRequest<'a, 'r> for ApiKey {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match request_key(request) {
+            Some(key) => Outcome::Success(key),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// A key with the `admin` scope.
+pub struct Admin(pub ApiKey);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match request_key(request) {
+            Some(key) if key.has(Scope::Admin) => Outcome::Success(Admin(key)),
+            Some(..) => Outcome::Failure((Status::Forbidden, ())),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// Guard for the upload routes: a key with the `upload` scope, or nobody if
+/// anonymous uploads are allowed or the user is logged in. A bad key is
+/// refused either way, and so are banned keys and addresses.
+pub struct Uploader {
+    pub key: Option<ApiKey>,
+    /// See `moderation::hash_ip`.
+    pub ip_hash: Option<String>,
+}
+
+impl Uploader {
+    /// Records the key and the address hash on the paste.
+    pub fn attribute(&self, options: &mut UploadOptions) {
+        options.meta.api_key = self.key.as_ref().map(|key| key.id.clone());
+        options.meta.ip_hash = self.ip_hash.clone();
+    }
+}
+
+fn request_ip_hash(request: &Request<'_>) -> Option<String> {
+    request.guard::<ClientIp>().succeeded().and_then(|client| client.0).map(|ip| hash_ip(&ip))
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Uploader {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let ip_hash = request_ip_hash(request);
+        if ip_hash.as_ref().map_or(false, |hash| is_banned(BanKind::Ip, hash)) {
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+        let key = match request_key(request) {
+            None if bearer(request).is_none() => match anonymous_uploads(request) || request.guard::<User>().is_success() {
+                true => None,
+                false => return Outcome::Failure((Status::Unauthorized, ())),
+            },
+            Some(key) if key.has(Scope::Upload) && !is_banned(BanKind::Key, &key.id) => Some(key),
+            Some(..) => return Outcome::Failure((Status::Forbidden, ())),
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        Outcome::Success(Uploader { key, ip_hash })
+    }
+}
+
+/// Guard for the routes that change an existing paste or channel: refuses
+/// banned addresses and banned keys like `Uploader`, but needs no key.
+pub struct NotBanned;
+
+impl<'a, 'r> FromRequest<'a, 'r> for NotBanned {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let ip_banned = request_ip_hash(request).map_or(false, |hash| is_banned(BanKind::Ip, &hash));
+        let key_banned = request_key(request).map_or(false, |key| is_banned(BanKind::Key, &key.id));
+        match ip_banned || key_banned {
+            true => Outcome::Failure((Status::Forbidden, ())),
+            false => Outcome::Success(NotBanned),
+        }
+    }
+}
+
+/// `anonymous_uploads` from `Rocket.toml`.
+pub struct KeyConfig {
+    pub anonymous_uploads: bool,
+}
+
+pub fn anonymous_uploads(request: &Request<'_>) -> bool {
+    request.guard::<State<KeyConfig>>().succeeded().map_or(true, |config| config.anonymous_uploads)
+}
+
+#[derive(Deserialize)]
+struct NewKey {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize)]
+struct MintedKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    token: String,
+}
+
+#[derive(Debug, Responder)]
+enum KeyError {
+    #[response(status = 400)]
+    BadRequest(String),
+    Io(Debug<io::Error>),
+}
+
+#[post("/api/keys", format = "json", data = "<new>")]
+fn create(_admin: Admin, new: Json<NewKey>) -> Result<status::Created<Json<MintedKey>>, KeyError> {
+    let name = new.name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || name.chars().any(char::is_control) {
+        return Err(KeyError::BadRequest(format!("key names are 1 to {} characters\n", MAX_NAME_LENGTH)));
+    }
+    let (key, token) = mint(name, &new.scopes).map_err(|e| KeyError::Io(Debug(e)))?;
+    let url = format!("/api/keys/{id}", id = key.id);
+    Ok(status::Created(url, Some(Json(MintedKey { key, token }))))
+}
+
+#[get("/api/keys")]
+fn keys(_admin: Admin) -> Json<Vec<ApiKey>> {
+    Json(list())
+}
+
+#[delete("/api/keys/<id>")]
+fn revoke(_admin: Admin, id: PasteID<'_>) -> Result<Status, Debug<io::Error>> {
+    match fs::remove_file(key_path(&id.to_string())) {
+        Ok(()) => Ok(Status::NoContent),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Status::NotFound),
+        Err(e) => Err(Debug(e)),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, keys, revoke]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("api keys", |rocket| {
+        let anonymous_uploads = rocket.config().get_bool("anonymous_uploads").unwrap_or(true);
+        Ok(rocket.manage(KeyConfig { anonymous_uploads }))
+    })
+}
+
+/// For `pastebin mint-admin-key`: a key with every scope.
+pub fn mint_admin(name: &str) -> io::Result<String> {
+    mint(name, &[Scope::Upload, Scope::Delete, Scope::Admin]).map(|(_, token)| token)
+}
+
+/// Points out how to get in when there is no admin key yet.
+pub fn check_admin_key() -> impl Fairing {
+    AdHoc::on_launch("admin key", |_| {
+        if !list().iter().any(|key| key.has(Scope::Admin)) {
+            eprintln!("there is no admin API key yet; run `pastebin mint-admin-key` to make one");
+        }
+    })
+}
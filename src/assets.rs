@@ -0,0 +1,161 @@
+//! Everything under `static/`, served at `/static/<path>`. Each file is also
+//! reachable under a fingerprinted name with a hash of its contents, e.g.
+//! `js/clipboard.1f0c9e2a7b.js`, which is what `url` hands to the views; those
+//! are cached for a year, the plain names are revalidated.
+//!
+//! A `<file>.br` or `<file>.gz` next to a file is sent instead when the
+//! browser accepts it and it is not older than the file. The third-party CSS
+//! and JS are served from `static/vendor`, so pages never load anything from
+//! another origin; `./make.sh assets` downloads the ones missing there (to be
+//! committed) and writes the compressed copies. Files are scanned once, at
+//! the first request.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, content::Content, Responder};
+use rocket::Route;
+use sha2::{Digest, Sha256};
+
+const STATIC_DIR: &str = "static";
+const FINGERPRINT_LENGTH: usize = 10;
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "public, no-cache";
+/// Precompressed variants, in order of preference.
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+struct StaticFile {
+    path: PathBuf,
+    fingerprinted: bool,
+}
+
+#[derive(Default)]
+struct Assets {
+    /// Plain and fingerprinted names, relative to `static/`.
+    files: HashMap<String, StaticFile>,
+    /// Plain name to the fingerprinted URL.
+    urls: HashMap<String, String>,
+}
+
+lazy_static! {
+    static ref ASSETS: Assets = Assets::scan(Path::new(STATIC_DIR));
+}
+
+fn is_variant(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    ENCODINGS.iter().any(|(_, suffix)| extension == Some(*suffix))
+}
+
+/// `js/clipboard.js` becomes `js/clipboard.<hash>.js`.
+fn fingerprinted_name(name: &str, hash: &str) -> String {
+    match name.rfind('.').filter(|dot| !name[*dot..].contains('/')) {
+        Some(dot) => format!("{}.{}{}", &name[..dot], hash, &name[dot..]),
+        None => format!("{}.{}", name, hash),
+    }
+}
+
+impl Assets {
+    fn scan(root: &Path) -> Assets {
+        let mut assets = Assets::default();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).into_iter().flatten().filter_map(Result::ok) {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if !is_variant(&path) {
+                    assets.add(root, path);
+                }
+            }
+        }
+        assets
+    }
+
+    fn add(&mut self, root: &Path, path: PathBuf) {
+        let name = match path.strip_prefix(root).ok().and_then(|name| name.to_str()) {
+            Some(name) => name.replace('\\', "/"),
+            None => return,
+        };
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(..) => return,
+        };
+        let hash: String = Sha256::digest(&contents).iter().map(|byte| format!("{:02x}", byte)).collect();
+        let fingerprinted = fingerprinted_name(&name, &hash[..FINGERPRINT_LENGTH]);
+        self.urls.insert(name.clone(), format!("/{}/{}", STATIC_DIR, fingerprinted));
+        self.files.insert(fingerprinted, StaticFile { path: path.clone(), fingerprinted: true });
+        self.files.insert(name, StaticFile { path, fingerprinted: false });
+    }
+}
+
+/// URL of the static file `name`, e.g. `js/clipboard.js`.
+pub fn url(name: &str) -> String {
+    match ASSETS.urls.get(name) {
+        Some(url) => url.clone(),
+        None => format!("/{}/{}", STATIC_DIR, name),
+    }
+}
+
+fn accepts(request: &Request<'_>, encoding: &str) -> bool {
+    request.headers().get("Accept-Encoding").flat_map(|value| value.split(',')).any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        parts.next() == Some(encoding) && parts.all(|param| param != "q=0")
+    })
+}
+
+/// A precompressed variant of `path` the client accepts, if it is current.
+fn variant(request: &Request<'_>, path: &Path) -> Option<(&'static str, PathBuf)> {
+    let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+    ENCODINGS.iter()
+        .filter(|(encoding, _)| accepts(request, encoding))
+        .map(|(encoding, suffix)| (*encoding, PathBuf::from(format!("{}.{}", path.display(), suffix))))
+        .find(|(_, variant)| {
+            fs::metadata(variant).and_then(|meta| meta.modified()).map_or(false, |time| time >= modified)
+        })
+}
+
+pub struct Asset(&'static StaticFile);
+
+impl<'r> Responder<'r> for Asset {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let StaticFile { path, fingerprinted } = self.0;
+        let content_type = path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ContentType::from_extension)
+            .unwrap_or(ContentType::Binary);
+        let (encoding, file) = match variant(request, path) {
+            Some((encoding, variant)) => (Some(encoding), File::open(variant)),
+            None => (None, File::open(path)),
+        };
+        let file = file.map_err(|_| Status::NotFound)?;
+        let mut response = Content(content_type, file).respond_to(request)?;
+        if let Some(encoding) = encoding {
+            response.set_raw_header("Content-Encoding", encoding);
+        }
+        response.set_raw_header("Vary", "Accept-Encoding");
+        response.set_raw_header("Cache-Control", if *fingerprinted { IMMUTABLE } else { REVALIDATE });
+        Ok(response)
+    }
+}
+
+fn find(name: &str) -> Option<Asset> {
+    ASSETS.files.get(name).map(Asset)
+}
+
+#[get("/static/<path..>")]
+fn asset(path: PathBuf) -> Option<Asset> {
+    find(path.to_str()?)
+}
+
+/// Browsers ask for it at the root.
+#[get("/favicon.ico")]
+fn favicon() -> Option<Asset> {
+    find("icons/favicon.ico")
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![asset, favicon]
+}
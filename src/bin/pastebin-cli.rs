@@ -0,0 +1,376 @@
+//! Command-line client for the pastebin server.
+//!
+//!     make 2>&1 | pastebin-cli copy --expiry 1d --qr
+//!     pastebin-cli paste <id>
+//!
+//! Settings live in `~/.config/pastebin/config` as `key=value` lines, and
+//! the owner tokens of uploaded pastes in `pastes` next to it, so that
+//! `edit`, `delete` and `list` work on pastes uploaded from this machine.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+
+#[allow(dead_code)]
+#[path = "../paste_id.rs"]
+mod paste_id;
+
+const DEFAULT_SERVER: &str = "https://copy.red";
+
+const USAGE: &str = "\
+usage: pastebin-cli <command> [arguments]
+
+commands:
+    copy [--<option> <value>]... [--burn] [--qr] [FILE]...
+                            upload each FILE, or stdin, and print the URLs
+    paste <id>              print a paste
+    edit <id> [FILE]        replace a paste uploaded from here with FILE or stdin
+    delete <id>             delete a paste uploaded from here, or with api_key
+    list                    list the pastes uploaded from here
+    channel push [TEXT]     push TEXT, or stdin, to the clipboard channel
+    channel pull            print the latest entry of the clipboard channel
+    channel pair [CODE]     pair with another device; shows a code if none is given
+
+Options for copy are those of POST /api/paste: expiry, burn, syntax, title,
+filename, visibility and id_length. <id> may also be a paste URL.
+
+The config file is $PASTEBIN_CONFIG or ~/.config/pastebin/config:
+    server=https://copy.red
+    api_key=pb_<id>_<secret>
+    channel=<name>
+    channel_token=<token>
+";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn parse_pairs(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Opens a file for writing that only the user may read, as the config
+/// holds the API key and channel token and `pastes` the owner tokens.
+fn open_private(path: &Path, append: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).append(append).truncate(!append);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let file = options.open(path)?;
+    // The mode only applies to new files; older ones may be world-readable.
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+/// `key=value` lines, like the server's `.meta` files.
+struct Config {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    fn load() -> Config {
+        let path = match env::var_os("PASTEBIN_CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None => env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".config"))
+                .join("pastebin/config"),
+        };
+        let values = fs::read_to_string(&path).map(|text| parse_pairs(&text)).unwrap_or_default();
+        Config { path, values }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text: String = self.values.iter().map(|(key, value)| format!("{}={}\n", key, value)).collect();
+        open_private(&self.path, false)?.write_all(text.as_bytes())
+    }
+
+    fn server(&self) -> &str {
+        self.get("server").unwrap_or(DEFAULT_SERVER).trim_end_matches('/')
+    }
+
+    /// Uploaded pastes, one `<url> <owner token>` line each.
+    fn pastes_path(&self) -> PathBuf {
+        self.path.with_file_name("pastes")
+    }
+
+    fn pastes(&self) -> Vec<(String, String)> {
+        fs::read_to_string(self.pastes_path())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(url, token)| (url.to_string(), token.to_string()))
+            .collect()
+    }
+
+    fn owner_token(&self, id: &str) -> Result<String> {
+        self.pastes().into_iter()
+            .find(|(url, _)| paste_id(url).ok().as_deref() == Some(id))
+            .map(|(_, token)| token)
+            .ok_or_else(|| format!("{} was not uploaded from here", id).into())
+    }
+
+    /// Adds the configured API key, if any.
+    fn authorize(&self, request: ureq::Request) -> ureq::Request {
+        match self.get("api_key") {
+            Some(key) => request.set("Authorization", &format!("Bearer {}", key)),
+            None => request,
+        }
+    }
+
+    fn channel(&self) -> Result<(&str, &str)> {
+        match (self.get("channel"), self.get("channel_token")) {
+            (Some(channel), Some(token)) => Ok((channel, token)),
+            _ => Err("no channel configured; run `pastebin-cli channel pair` first".into()),
+        }
+    }
+}
+
+/// The ID from a paste ID or URL, e.g. `https://copy.red/api/<id>/notes.txt`.
+fn paste_id(arg: &str) -> Result<String> {
+    let segments: Vec<&str> = arg.trim().split('/').filter(|s| !s.is_empty()).collect();
+    let id = match segments.iter().position(|&s| s == "api") {
+        Some(i) => segments.get(i + 1),
+        None => segments.last(),
+    };
+    match id {
+        Some(id) if paste_id::valid_id(id) => Ok(id.to_string()),
+        _ => Err(format!("`{}` is not a paste ID or URL", arg).into()),
+    }
+}
+
+/// Turns error statuses into errors carrying the server's explanation.
+fn check(result: std::result::Result<ureq::Response, ureq::Error>) -> Result<ureq::Response> {
+    match result {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            Err(format!("server answered {}: {}", status, body.trim()).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn input(file: Option<&String>) -> Result<Box<dyn Read>> {
+    match file.map(String::as_str) {
+        None | Some("-") => Ok(Box::new(io::stdin())),
+        Some(file) => Ok(Box::new(File::open(file).map_err(|e| format!("{}: {}", file, e))?)),
+    }
+}
+
+fn print_qr(url: &str) -> Result<()> {
+    // Inverted, since terminals are usually light text on a dark background.
+    let code = QrCode::new(url)?.render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!("{}", code);
+    Ok(())
+}
+
+/// `id_length` becomes `X-Paste-Id-Length`.
+fn option_header(option: &str) -> String {
+    let words: Vec<String> = option.split(|c| c == '_' || c == '-')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|c| c.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+        })
+        .collect();
+    format!("X-Paste-{}", words.join("-"))
+}
+
+fn upload(config: &Config, body: Box<dyn Read>, options: &[(String, String)]) -> Result<String> {
+    let mut request = config.authorize(ureq::post(&format!("{}/api/paste", config.server())));
+    for (option, value) in options {
+        request = request.set(&option_header(option), value);
+    }
+    let response = check(request.send(body))?;
+    let token = response.header("X-Owner-Token").map(String::from);
+    let url = response.into_string()?.trim().to_string();
+    if let Some(token) = token {
+        let mut pastes = open_private(&config.pastes_path(), true)?;
+        writeln!(pastes, "{} {}", url, token)?;
+    }
+    Ok(url)
+}
+
+fn copy(config: &Config, args: &[String]) -> Result<()> {
+    let (mut options, mut files, mut qr) = (Vec::new(), Vec::new(), false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some("qr") => qr = true,
+            Some("burn") => options.push(("burn".to_string(), "true".to_string())),
+            Some(option) => {
+                let value = args.next().ok_or_else(|| format!("missing value for --{}", option))?;
+                options.push((option.to_string(), value.clone()));
+            }
+            None => files.push(arg),
+        }
+    }
+    if let Some(dir) = config.pastes_path().parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let uploads: Vec<Option<&String>> = match files.is_empty() {
+        true => vec![None],
+        false => files.into_iter().map(Some).collect(),
+    };
+    for file in uploads {
+        let mut options = options.clone();
+        let name = file.and_then(|file| Path::new(file).file_name()).and_then(|name| name.to_str());
+        if let (Some(name), false) = (name, options.iter().any(|(option, _)| option == "filename")) {
+            options.push(("filename".to_string(), name.to_string()));
+        }
+        let url = upload(config, input(file)?, &options)?;
+        println!("{}", url);
+        if qr {
+            print_qr(&url)?;
+        }
+    }
+    Ok(())
+}
+
+fn paste(config: &Config, id: &str) -> Result<()> {
+    let response = check(ureq::get(&format!("{}/api/{}", config.server(), id)).call())?;
+    io::copy(&mut response.into_reader(), &mut io::stdout())?;
+    Ok(())
+}
+
+fn edit(config: &Config, id: &str, file: Option<&String>) -> Result<()> {
+    let token = config.owner_token(id)?;
+    let request = ureq::put(&format!("{}/api/{}", config.server(), id)).set("X-Owner-Token", &token);
+    check(request.send(input(file)?))?;
+    Ok(())
+}
+
+fn delete(config: &Config, id: &str) -> Result<()> {
+    let request = ureq::delete(&format!("{}/api/{}", config.server(), id));
+    let request = match (config.owner_token(id), config.get("api_key")) {
+        (Ok(token), _) => request.set("X-Owner-Token", &token),
+        (Err(..), Some(..)) => config.authorize(request),
+        (Err(e), None) => return Err(e),
+    };
+    check(request.call())?;
+    let remaining: String = config.pastes().into_iter()
+        .filter(|(url, _)| paste_id(url).ok().as_deref() != Some(id))
+        .map(|(url, token)| format!("{} {}\n", url, token))
+        .collect();
+    open_private(&config.pastes_path(), false)?.write_all(remaining.as_bytes())?;
+    Ok(())
+}
+
+fn list(config: &Config) -> Result<()> {
+    for (url, _) in config.pastes() {
+        println!("{}", url);
+    }
+    Ok(())
+}
+
+fn channel(config: &mut Config, args: &[String]) -> Result<()> {
+    match (args.first().map(String::as_str), args.get(1)) {
+        (Some("push"), text) => {
+            let (name, token) = config.channel()?;
+            let request = ureq::post(&format!("{}/c/{}", config.server(), name)).set("X-Channel-Token", token);
+            match text {
+                Some(text) if text != "-" => check(request.send_string(text))?,
+                _ => check(request.send(io::stdin()))?,
+            };
+        }
+        (Some("pull"), None) => {
+            let (name, token) = config.channel()?;
+            let request = ureq::get(&format!("{}/c/{}", config.server(), name)).set("X-Channel-Token", token);
+            io::copy(&mut check(request.call())?.into_reader(), &mut io::stdout())?;
+        }
+        (Some("pair"), code) => {
+            let url = match code {
+                Some(code) => format!("{}/api/pair/{}", config.server(), code),
+                None => format!("{}/api/pair", config.server()),
+            };
+            let paired: serde_json::Value = serde_json::from_reader(check(ureq::post(&url).call())?.into_reader())?;
+            if let Some(code) = paired["code"].as_str() {
+                println!("enter {} on the other device within {}s", code, paired["expires_in"]);
+            }
+            match (paired["channel"].as_str(), paired["token"].as_str()) {
+                (Some(channel), Some(token)) => {
+                    config.set("channel", channel);
+                    config.set("channel_token", token);
+                    config.save()?;
+                }
+                _ => return Err("unexpected answer from the server".into()),
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let mut config = Config::load();
+    let command = args.first().map(String::as_str);
+    let id = || args.get(1).ok_or_else(|| Box::<dyn Error>::from(USAGE)).and_then(|arg| paste_id(arg));
+    match command {
+        Some("copy") => copy(&config, &args[1..]),
+        Some("paste") => paste(&config, &id()?),
+        Some("edit") => edit(&config, &id()?, args.get(2)),
+        Some("delete") => delete(&config, &id()?),
+        Some("list") => list(&config),
+        Some("channel") => channel(&mut config, &args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("pastebin-cli: {}", e.to_string().trim_end());
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{option_header, paste_id};
+
+    #[test]
+    fn paste_ids_from_urls() {
+        assert_eq!(paste_id("abc").unwrap(), "abc");
+        assert_eq!(paste_id("https://copy.red/abc\n").unwrap(), "abc");
+        assert_eq!(paste_id("https://copy.red/api/abc/notes.txt").unwrap(), "abc");
+        assert!(paste_id("https://copy.red/api/a.b").is_err());
+        assert!(paste_id("").is_err());
+    }
+
+    #[test]
+    fn option_headers() {
+        assert_eq!(option_header("expiry"), "X-Paste-Expiry");
+        assert_eq!(option_header("id_length"), "X-Paste-Id-Length");
+    }
+}
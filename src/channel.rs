@@ -0,0 +1,256 @@
+//! Named clipboard channels: a stable, token-protected address per user or
+//! device pair, instead of a new random paste ID for every copy.
+//!
+//!     echo hi | curl -H "X-Channel-Token: $TOKEN" --data-binary @- https://copy.red/c/laptop
+//!     curl -H "X-Channel-Token: $TOKEN" https://copy.red/c/laptop
+//!
+//! The first push to an unused name claims it. The token is either chosen by
+//! the client (`X-Channel-Token`) or generated and returned in that header;
+//! only its SHA-256 is stored. Entries live in `upload/.channels/<name>/`,
+//! the oldest beyond `channel_history` being dropped. New entries are also
+//! published to live subscribers, see `ws.rs`. Pushing counts as an upload
+//! for rate limits and bans.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use rocket::data::Data;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{RawStr, Status};
+use rocket::request::{self, FromParam, FromRequest, Request};
+use rocket::response::{content::Plain, Debug, Response};
+use rocket::{Outcome, Route, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use crate::api_key::{hash_secret, NotBanned};
+use crate::events::Hub;
+use crate::meta::{new_token, tokens_match, unix_now};
+use crate::rate_limit::UploadLimit;
+
+const CHANNEL_DIR: &str = "upload/.channels";
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Settings read from `channel_history` and `channel_max_size` (bytes) in
+/// `Rocket.toml`. Managed as an `Arc`, as the WebSocket listener shares it.
+pub struct Channels {
+    history: usize,
+    pub max_size: u64,
+    /// Serializes stores, which number entries.
+    lock: Mutex<()>,
+    hub: Hub<Entry>,
+}
+
+impl Channels {
+    /// Adds an entry to an existing channel and sends it to subscribers.
+    pub fn store(&self, name: &str, text: &[u8]) -> io::Result<()> {
+        {
+            let _guard = self.lock.lock().unwrap();
+            Channel::open(name).push(text, self.history)?;
+        }
+        let text = String::from_utf8_lossy(text).into_owned();
+        self.hub.publish(name, Entry { time: unix_now(), text });
+        Ok(())
+    }
+
+    pub fn subscribe(&self, name: &str) -> Receiver<Entry> {
+        self.hub.subscribe(name)
+    }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub struct ChannelName<'a>(&'a str);
+
+impl<'a> FromParam<'a> for ChannelName<'a> {
+    type Error = &'a RawStr;
+
+    fn from_param(param: &'a RawStr) -> Result<ChannelName<'a>, &'a RawStr> {
+        match valid_name(param) {
+            true => Ok(ChannelName(param.as_str())),
+            false => Err(param),
+        }
+    }
+}
+
+/// The `X-Channel-Token` header; requests without one are forwarded.
+pub struct ChannelToken(String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ChannelToken {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Channel-Token") {
+            Some(token) if !token.is_empty() && !token.contains(char::is_control) => {
+                Outcome::Success(ChannelToken(token.to_string()))
+            }
+            Some(..) => Outcome::Failure((Status::BadRequest, ())),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    /// Unix time the entry was pushed.
+    pub time: u64,
+    pub text: String,
+}
+
+pub struct Channel {
+    dir: PathBuf,
+}
+
+impl Channel {
+    pub fn open(name: &str) -> Channel {
+        Channel { dir: PathBuf::from(format!("{dir}/{name}", dir = CHANNEL_DIR, name = name)) }
+    }
+
+    /// The `hash_secret` of the channel's token.
+    fn token_hash(&self) -> Option<String> {
+        fs::read_to_string(self.dir.join("token")).ok()
+    }
+
+    /// Creates the channel with `token` unless someone claimed it already.
+    pub fn claim(&self, token: &str) -> io::Result<bool> {
+        fs::create_dir_all(&self.dir)?;
+        match OpenOptions::new().write(true).create_new(true).open(self.dir.join("token")) {
+            Ok(mut file) => file.write_all(hash_secret(token).as_bytes()).map(|_| true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deletes the channel with its token and entries.
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_dir_all(&self.dir)
+    }
+
+    pub fn authorize(&self, token: &str) -> Result<(), Status> {
+        match self.token_hash() {
+            Some(expected) if tokens_match(&expected, &hash_secret(token)) => Ok(()),
+            Some(..) => Err(Status::Forbidden),
+            None => Err(Status::NotFound),
+        }
+    }
+
+    /// Entry files, oldest first. They are named by a zero-padded sequence
+    /// number, so name order is push order.
+    fn entries(&self) -> io::Result<Vec<PathBuf>> {
+        let mut entries: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().and_then(|name| name.to_str())
+                .map_or(false, |name| name.chars().all(|c| c.is_ascii_digit())))
+            .collect();
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn push(&self, text: &[u8], history: usize) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        let next = entries.last()
+            .and_then(|path| path.file_name()?.to_str()?.parse::<u64>().ok())
+            .map_or(0, |seq| seq + 1);
+        fs::write(self.dir.join(format!("{:020}", next)), text)?;
+        entries.push(self.dir.join(format!("{:020}", next)));
+        let excess = entries.len().saturating_sub(history.max(1));
+        for old in &entries[..excess] {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    pub fn latest(&self) -> io::Result<Option<File>> {
+        match self.entries()?.last() {
+            Some(path) => File::open(path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn history(&self) -> io::Result<Vec<Entry>> {
+        let mut history = Vec::new();
+        for path in self.entries()?.iter().rev() {
+            let time = fs::metadata(path)?.modified()?
+                .duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+            history.push(Entry { time, text });
+        }
+        Ok(history)
+    }
+}
+
+#[post("/c/<name>", data = "<data>")]
+fn push(
+    _limit: UploadLimit,
+    _banned: NotBanned,
+    name: ChannelName<'_>,
+    token: Option<ChannelToken>,
+    data: Data,
+    channels: State<Arc<Channels>>,
+) -> Result<Response<'static>, Debug<io::Error>> {
+    let mut text = Vec::new();
+    data.open().take(channels.max_size + 1).read_to_end(&mut text)?;
+    if text.len() as u64 > channels.max_size {
+        return Ok(Response::build().status(Status::PayloadTooLarge).finalize());
+    }
+
+    let channel = Channel::open(name.0);
+    let token = token.map_or_else(new_token, |token| token.0);
+    let created = channel.claim(&token)?;
+    if let Err(status) = channel.authorize(&token) {
+        return Ok(Response::build().status(status).finalize());
+    }
+    channels.store(name.0, &text)?;
+
+    let mut response = Response::build();
+    match created {
+        true => response.status(Status::Created).raw_header("X-Channel-Token", token),
+        false => response.status(Status::NoContent),
+    };
+    Ok(response.finalize())
+}
+
+#[get("/c/<name>")]
+fn pull(name: ChannelName<'_>, token: ChannelToken) -> Result<Result<Plain<File>, Status>, Debug<io::Error>> {
+    let channel = Channel::open(name.0);
+    if let Err(status) = channel.authorize(&token.0) {
+        return Ok(Err(status));
+    }
+    Ok(channel.latest()?.map(Plain).ok_or(Status::NotFound))
+}
+
+#[get("/c/<name>/history")]
+fn history(name: ChannelName<'_>, token: ChannelToken) -> Result<Result<Json<Vec<Entry>>, Status>, Debug<io::Error>> {
+    let channel = Channel::open(name.0);
+    if let Err(status) = channel.authorize(&token.0) {
+        return Ok(Err(status));
+    }
+    Ok(Ok(Json(channel.history()?)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![push, pull, history]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("clipboard channels", |rocket| {
+        let history = rocket.config().get_int("channel_history").unwrap_or(20);
+        let max_size = rocket.config().get_int("channel_max_size").unwrap_or(1 << 20);
+        if fs::create_dir_all(CHANNEL_DIR).is_err() {
+            return Err(rocket);
+        }
+        Ok(rocket.manage(Arc::new(Channels {
+            history: history.max(1) as usize,
+            max_size: max_size.max(0) as u64,
+            lock: Mutex::new(()),
+            hub: Hub::default(),
+        })))
+    })
+}
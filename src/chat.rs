@@ -0,0 +1,210 @@
+//! Discussion threads attached to pastes, e.g. for code review comments.
+//!
+//! Messages are stored as JSON lines in `upload/<id>.chat`, which
+//! `meta::delete` removes together with the paste, so a thread expires (or
+//! burns) with it. New messages are also published as `chat` events.
+//!
+//!     curl -H 'Content-Type: application/json' \
+//!          -d '{"nick": "ann", "line": 12, "text": "off by one?"}' \
+//!          https://copy.red/api/<id>/chat
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Debug, Redirect, Responder, Response};
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::csrf::CsrfForm;
+use crate::events::{EventHub, PasteEvent};
+use crate::meta::{self, unix_now, PasteMeta};
+use crate::password::{Locked, Password, Passwords};
+use crate::paste_id::PasteID;
+use crate::rate_limit::{ClientIp, RateLimiter, ReadLimit};
+
+const MAX_NICK_LENGTH: usize = 32;
+const MAX_TEXT_LENGTH: usize = 2000;
+/// Further messages are refused once a thread is this long.
+const MAX_MESSAGES: usize = 500;
+const DEFAULT_NICK: &str = "anonymous";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub nick: String,
+    /// Unix time the message was posted.
+    pub time: u64,
+    /// Line of the paste the message refers to, counting from 1.
+    pub line: Option<u32>,
+    pub text: String,
+}
+
+/// A message as posted, either by the chatbox form or as JSON.
+#[derive(Deserialize, FromForm)]
+pub struct NewMessage {
+    #[serde(default)]
+    nick: String,
+    line: Option<u32>,
+    text: String,
+}
+
+impl NewMessage {
+    fn validate(self) -> Result<Message, String> {
+        let nick = match self.nick.trim() {
+            "" => DEFAULT_NICK,
+            nick => nick,
+        };
+        if nick.chars().count() > MAX_NICK_LENGTH || nick.chars().any(char::is_control) {
+            return Err(format!("nick must be a single line of at most {} characters\n", MAX_NICK_LENGTH));
+        }
+        let text = self.text.trim_end();
+        if text.trim().is_empty() || text.chars().count() > MAX_TEXT_LENGTH {
+            return Err(format!("message must have 1 to {} characters\n", MAX_TEXT_LENGTH));
+        }
+        if self.line == Some(0) {
+            return Err("line numbers start at 1\n".into());
+        }
+        Ok(Message { nick: nick.to_string(), time: unix_now(), line: self.line, text: text.to_string() })
+    }
+}
+
+/// Serializes writes to the thread files and limits posting per address.
+pub struct Chat {
+    lock: Mutex<()>,
+    limiter: RateLimiter,
+}
+
+impl Default for Chat {
+    fn default() -> Chat {
+        Chat { lock: Mutex::new(()), limiter: RateLimiter::new(10, Duration::from_secs(60)) }
+    }
+}
+
+pub enum PostError {
+    NotFound,
+    Invalid(String),
+    ThreadFull,
+    TooManyRequests(Duration),
+    Locked(Locked),
+    Io(io::Error),
+}
+
+impl From<io::Error> for PostError {
+    fn from(error: io::Error) -> Self {
+        PostError::Io(error)
+    }
+}
+
+impl<'r> Responder<'r> for PostError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            PostError::NotFound => Err(Status::NotFound),
+            PostError::Invalid(message) => Response::build_from(message.respond_to(request)?)
+                .status(Status::BadRequest)
+                .ok(),
+            PostError::ThreadFull => Response::build_from("this thread is full\n".respond_to(request)?)
+                .status(Status::Conflict)
+                .ok(),
+            PostError::TooManyRequests(wait) => Response::build()
+                .status(Status::TooManyRequests)
+                .raw_header("Retry-After", (wait.as_secs() + 1).to_string())
+                .ok(),
+            PostError::Locked(locked) => locked.respond_to(request),
+            PostError::Io(error) => Debug(error).respond_to(request),
+        }
+    }
+}
+
+fn chat_path(id: &str) -> String {
+    format!("upload/{id}.chat", id = id)
+}
+
+/// Messages of a paste, oldest first.
+pub fn load(id: &str) -> Vec<Message> {
+    fs::read_to_string(chat_path(id))
+        .map(|text| text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+        .unwrap_or_default()
+}
+
+fn post(id: &str, client: &ClientIp, new: NewMessage, chat: &Chat, hub: &EventHub) -> Result<Message, PostError> {
+    if !meta::exists(id) {
+        return Err(PostError::NotFound);
+    }
+    chat.limiter.check(&client.key()).map_err(PostError::TooManyRequests)?;
+    let message = new.validate().map_err(PostError::Invalid)?;
+
+    let _guard = chat.lock.lock().unwrap();
+    if load(id).len() >= MAX_MESSAGES {
+        return Err(PostError::ThreadFull);
+    }
+    let mut line = serde_json::to_string(&message).map_err(io::Error::from)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(chat_path(id))?.write_all(line.as_bytes())?;
+    hub.publish(id, PasteEvent::Chat(message.clone()));
+    Ok(message)
+}
+
+/// Checks the password of a protected paste, if it still exists.
+fn unlock(id: &str, client: &ClientIp, password: &Password, passwords: &Passwords) -> Result<(), Locked> {
+    match PasteMeta::load_live(id) {
+        Some(meta) => passwords.unlock(id, &meta, client, password.0.as_deref()),
+        None => Ok(()),
+    }
+}
+
+#[get("/api/<id>/chat")]
+fn messages(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    client: ClientIp,
+    password: Password,
+    passwords: State<Passwords>,
+) -> Result<Option<Json<Vec<Message>>>, Locked> {
+    let id = id.to_string();
+    unlock(&id, &client, &password, &passwords)?;
+    match meta::exists(&id) {
+        true => Ok(Some(Json(load(&id)))),
+        false => Ok(None),
+    }
+}
+
+#[post("/api/<id>/chat", format = "json", data = "<message>")]
+fn post_json(
+    id: PasteID<'_>,
+    message: Json<NewMessage>,
+    client: ClientIp,
+    password: Password,
+    passwords: State<Passwords>,
+    chat: State<Chat>,
+    hub: State<EventHub>,
+) -> Result<status::Created<Json<Message>>, PostError> {
+    let id = id.to_string();
+    unlock(&id, &client, &password, &passwords).map_err(PostError::Locked)?;
+    let message = post(&id, &client, message.into_inner(), &chat, &hub)?;
+    Ok(status::Created(format!("/api/{id}/chat", id = id), Some(Json(message))))
+}
+
+/// The chatbox form on the paste page, which works without JavaScript.
+#[post("/api/<id>/chat", format = "form", data = "<message>", rank = 2)]
+fn post_form(
+    id: PasteID<'_>,
+    message: CsrfForm<NewMessage>,
+    client: ClientIp,
+    password: Password,
+    passwords: State<Passwords>,
+    chat: State<Chat>,
+    hub: State<EventHub>,
+) -> Result<Redirect, PostError> {
+    let id = id.to_string();
+    unlock(&id, &client, &password, &passwords).map_err(PostError::Locked)?;
+    post(&id, &client, message.into_inner(), &chat, &hub)?;
+    Ok(Redirect::to(format!("/{id}#chat", id = id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![messages, post_json, post_form]
+}
@@ -0,0 +1,129 @@
+//! CSRF tokens for the HTML forms. The token is kept in a private `csrf`
+//! cookie, which is replaced whenever a session starts or ends, and forms
+//! carry it in a hidden `csrf` field, which `CsrfForm` compares with the
+//! cookie when it reads the form. Keeping it out of the URL keeps it out of
+//! logs and `Referer` headers.
+//!
+//! Routes authorized by a header (owner tokens, API keys, JSON bodies) can't
+//! be forged by another site and don't use it.
+
+use std::convert::Infallible;
+use std::ops::Deref;
+
+use maud::{html, Markup};
+use rocket::data::{self, Data, FromData, Transform, Transformed};
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::request::{self, Form, FormItems, FromForm, FromRequest, Request};
+use rocket::Outcome;
+
+use crate::meta::tokens_match;
+use crate::paste_id::PasteID;
+
+const CSRF_COOKIE: &str = "csrf";
+const TOKEN_LENGTH: usize = 32;
+
+/// The token to embed in the forms of a page. Sets the cookie if the
+/// browser has none yet.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The hidden field to put in every form of the page.
+    pub fn field(&self) -> Markup {
+        html! { input type="hidden" name="csrf" value=(self.0); }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfToken {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let mut cookies = request.cookies();
+        match cookies.get_private(CSRF_COOKIE) {
+            Some(cookie) => Outcome::Success(CsrfToken(cookie.value().to_string())),
+            None => Outcome::Success(rotate(&mut cookies)),
+        }
+    }
+}
+
+/// Replaces the token, e.g. when the user logs in or out.
+pub fn rotate(cookies: &mut Cookies<'_>) -> CsrfToken {
+    let token = PasteID::new(TOKEN_LENGTH).to_string();
+    cookies.add_private(Cookie::new(CSRF_COOKIE, token.clone()));
+    CsrfToken(token)
+}
+
+/// The token in the cookie, read before any guard can hold on to the
+/// cookies; see `fairing`.
+struct CookieToken(Option<String>);
+
+fn cookie_token<'r>(request: &'r Request<'_>) -> &'r Option<String> {
+    &request.local_cache(|| {
+        CookieToken(request.cookies().get_private(CSRF_COOKIE).map(|cookie| cookie.value().to_string()))
+    }).0
+}
+
+/// The body of forms that are nothing but a button.
+pub struct Button;
+
+impl<'f> FromForm<'f> for Button {
+    type Error = Infallible;
+    fn from_form(_: &mut FormItems<'f>, _: bool) -> Result<Self, Self::Error> {
+        Ok(Button)
+    }
+}
+
+/// Data guard for state-changing form routes: fails with 403 unless the
+/// form's `csrf` field matches the cookie, then parses the other fields
+/// into `T`.
+pub struct CsrfForm<T = Button>(pub T);
+
+impl<T> CsrfForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for CsrfForm<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'f, T: FromForm<'f>> FromData<'f> for CsrfForm<T> {
+    type Error = ();
+    type Owned = String;
+    type Borrowed = str;
+
+    fn transform(request: &Request<'_>, data: Data) -> Transform<data::Outcome<Self::Owned, Self::Error>> {
+        // Read the body the way `Form` does.
+        let outcome = match <Form<T> as FromData<'f>>::transform(request, data) {
+            Transform::Borrowed(outcome) | Transform::Owned(outcome) => outcome,
+        };
+        Transform::Borrowed(outcome.map_failure(|(status, _)| (status, ())))
+    }
+
+    fn from_data(request: &Request<'_>, outcome: Transformed<'f, Self>) -> data::Outcome<Self, Self::Error> {
+        let form = outcome.borrowed()?;
+        let given = FormItems::from(form)
+            .find(|item| item.key.as_str() == "csrf")
+            .map(|item| item.value.url_decode_lossy());
+        match (cookie_token(request), given) {
+            (Some(expected), Some(given)) if tokens_match(expected, &given) => {}
+            _ => return Outcome::Failure((Status::Forbidden, ())),
+        }
+        // Lenient, since the form also has the `csrf` field.
+        match T::from_form(&mut FormItems::from(form), false) {
+            Ok(value) => Outcome::Success(CsrfForm(value)),
+            Err(_) => Outcome::Failure((Status::UnprocessableEntity, ())),
+        }
+    }
+}
+
+/// Reads the cookie before the route's guards run: a `Cookies` guard hides
+/// the cookies from the data guard that comes after it.
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_request("csrf cookie", |request, _| {
+        cookie_token(request);
+    })
+}
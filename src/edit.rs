@@ -0,0 +1,79 @@
+//! Changing a paste after upload: `PUT /api/<id>` replaces its contents and
+//! `DELETE /api/<id>` removes it. Both take the owner token; a paste can
+//! also be deleted with an API key that may delete it.
+//!
+//!     curl -X PUT -H "X-Owner-Token: $TOKEN" --data-binary @notes.txt https://copy.red/api/<id>
+//!     curl -X DELETE -H "X-Owner-Token: $TOKEN" https://copy.red/api/<id>
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rocket::data::Data;
+use rocket::http::Status;
+use rocket::response::Debug;
+use rocket::{Route, State};
+
+use crate::api_key::{ApiKey, NotBanned};
+use crate::envelope;
+use crate::events::{EventHub, PasteEvent};
+use crate::meta::{self, authorize, OwnerToken, PasteMeta};
+use crate::moderation;
+use crate::paste_id::PasteID;
+
+/// Replaces the contents of a paste. An encrypted paste stays encrypted, so
+/// its new contents must be an envelope too. Banned content is refused and
+/// the old contents kept.
+#[put("/api/<id>", data = "<data>")]
+fn edit(_banned: NotBanned, id: PasteID<'_>, token: OwnerToken, data: Data, hub: State<EventHub>) -> Result<Status, Debug<io::Error>> {
+    let id = id.to_string();
+    let meta = match authorize(&id, &token) {
+        Ok(meta) => meta,
+        Err(status) => return Ok(status),
+    };
+    // Write next to the paste and rename, so readers never see half of it.
+    let filename = format!("upload/{id}", id = id);
+    let partial = format!("upload/{id}.partial", id = id);
+    match meta.encrypted {
+        true => match envelope::read(data.open()) {
+            Ok(envelope) => fs::write(&partial, envelope)?,
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => return Ok(Status::BadRequest),
+            Err(e) => return Err(Debug(e)),
+        },
+        false => {
+            data.stream_to_file(Path::new(&partial))?;
+        }
+    }
+    match moderation::check_content(Path::new(&partial)) {
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return Ok(Status::Forbidden),
+        result => result?,
+    }
+    fs::rename(&partial, &filename)?;
+    hub.publish(&id, PasteEvent::Edited);
+    Ok(Status::NoContent)
+}
+
+/// Authorized by the owner token, or by an API key that may delete it.
+#[delete("/api/<id>")]
+fn delete(id: PasteID<'_>, token: Option<OwnerToken>, key: Option<ApiKey>, hub: State<EventHub>) -> Status {
+    let id = id.to_string();
+    let authorized = match (token, key) {
+        (Some(token), _) => authorize(&id, &token).map(|_| ()),
+        (None, Some(key)) => match PasteMeta::load_live(&id) {
+            Some(meta) if key.may_delete(&meta) => Ok(()),
+            Some(..) => Err(Status::Forbidden),
+            None => Err(Status::NotFound),
+        },
+        (None, None) => Err(Status::Unauthorized),
+    };
+    if let Err(status) = authorized {
+        return status;
+    }
+    meta::delete(&id);
+    hub.publish(&id, PasteEvent::Deleted);
+    Status::NoContent
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![edit, delete]
+}
@@ -0,0 +1,58 @@
+//! End-to-end encrypted pastes. The browser encrypts with AES-GCM and
+//! uploads only this envelope; the key stays in the `#fragment` of the
+//! link, which browsers never send to the server.
+//!
+//!     {"v": 1, "alg": "AES-GCM", "iv": "<base64, 12 bytes>", "ct": "<base64>"}
+//!
+//! The server only checks the shape of the envelope. Error messages never
+//! include any of the uploaded data.
+
+use std::io::{self, Read};
+
+use serde::Deserialize;
+
+/// Largest accepted envelope, since it is read into memory to be checked.
+pub const MAX_SIZE: u64 = 4 << 20;
+const IV_LENGTH: usize = 12;
+/// AES-GCM appends a 16 byte authentication tag to the ciphertext.
+const TAG_LENGTH: usize = 16;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    v: u32,
+    alg: String,
+    iv: String,
+    ct: String,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn validate(data: &[u8]) -> io::Result<()> {
+    let envelope: Envelope = serde_json::from_slice(data)
+        .map_err(|_| invalid("encrypted pastes must be an envelope: {\"v\", \"alg\", \"iv\", \"ct\"}\n"))?;
+    if envelope.v != 1 || envelope.alg != "AES-GCM" {
+        return Err(invalid("unsupported envelope version or algorithm, expected v 1 and AES-GCM\n"));
+    }
+    match base64::decode(&envelope.iv) {
+        Ok(iv) if iv.len() == IV_LENGTH => {}
+        _ => return Err(invalid("envelope iv must be 12 bytes of base64\n")),
+    }
+    match base64::decode(&envelope.ct) {
+        Ok(ct) if ct.len() >= TAG_LENGTH => Ok(()),
+        _ => Err(invalid("envelope ct must be base64 ciphertext\n")),
+    }
+}
+
+/// Reads an envelope of at most `MAX_SIZE` bytes and checks it.
+pub fn read<R: Read>(data: R) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    data.take(MAX_SIZE + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_SIZE {
+        return Err(invalid("encrypted pastes are limited to 4 MiB\n"));
+    }
+    validate(&bytes)?;
+    Ok(bytes)
+}
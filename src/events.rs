@@ -0,0 +1,246 @@
+//! Publish/subscribe hub for paste changes, exposed as server-sent events on
+//! `GET /api/<id>/events`.
+//!
+//! An open stream, here or `?follow=1` in `live.rs`, holds on to one of
+//! rocket's workers, so `event_streams` in `Rocket.toml` caps how many may
+//! be open at once and `event_streams_per_address` how many one client may
+//! hold.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{ContentType, Status};
+use rocket::response::{content::Content, Stream};
+use rocket::{Route, State};
+
+use crate::chat::Message;
+use crate::meta::PasteMeta;
+use crate::password::{Locked, Password, Passwords};
+use crate::paste_id::PasteID;
+use crate::rate_limit::{ClientIp, ReadLimit};
+
+/// Interval of the keep-alive comments, which also notice gone clients.
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub enum PasteEvent {
+    Edited,
+    Appended { text: String },
+    Viewed,
+    Deleted,
+    Chat(Message),
+}
+
+impl PasteEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            PasteEvent::Edited => "edited",
+            PasteEvent::Appended { .. } => "appended",
+            PasteEvent::Viewed => "viewed",
+            PasteEvent::Deleted => "deleted",
+            PasteEvent::Chat(..) => "chat",
+        }
+    }
+
+    fn to_sse(&self) -> Vec<u8> {
+        let data = match self {
+            PasteEvent::Appended { text } => serde_json::json!({ "text": text }),
+            PasteEvent::Chat(message) => serde_json::to_value(message).unwrap_or_default(),
+            _ => serde_json::json!({}),
+        };
+        format!("event: {}\ndata: {}\n\n", self.name(), data).into_bytes()
+    }
+}
+
+/// Subscribers per key (a paste ID, or a channel name for `Hub<Entry>`).
+/// Senders whose receiver is gone are dropped on the next publish.
+pub struct Hub<T>(Mutex<HashMap<String, Vec<Sender<T>>>>);
+
+pub type EventHub = Hub<PasteEvent>;
+
+impl<T> Default for Hub<T> {
+    fn default() -> Hub<T> {
+        Hub(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<T: Clone> Hub<T> {
+    pub fn subscribe(&self, id: &str) -> Receiver<T> {
+        let (sender, receiver) = channel();
+        let mut subscribers = self.0.lock().unwrap();
+        subscribers.entry(id.to_string()).or_insert_with(Vec::new).push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, id: &str, event: T) {
+        let mut subscribers = self.0.lock().unwrap();
+        if let Some(senders) = subscribers.get_mut(id) {
+            senders.retain(|sender| sender.send(event.clone()).is_ok());
+            if senders.is_empty() {
+                subscribers.remove(id);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct OpenStreams {
+    total: usize,
+    by_address: HashMap<String, usize>,
+}
+
+/// Counts the open streams against `event_streams` and
+/// `event_streams_per_address`.
+pub struct Streams {
+    max: usize,
+    per_address: usize,
+    open: Arc<Mutex<OpenStreams>>,
+}
+
+impl Streams {
+    /// Takes a slot for a stream to `client`, which it keeps until dropped.
+    /// Refused with 429 when the client holds too many, 503 when everyone
+    /// together does.
+    pub fn open(&self, client: &ClientIp) -> Result<StreamSlot, Status> {
+        let key = client.key();
+        let mut open = self.open.lock().unwrap();
+        let held = open.by_address.get(&key).copied().unwrap_or(0);
+        if held >= self.per_address {
+            return Err(Status::TooManyRequests);
+        }
+        if open.total >= self.max {
+            return Err(Status::ServiceUnavailable);
+        }
+        open.total += 1;
+        open.by_address.insert(key.clone(), held + 1);
+        Ok(StreamSlot { open: self.open.clone(), key })
+    }
+}
+
+pub struct StreamSlot {
+    open: Arc<Mutex<OpenStreams>>,
+    key: String,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap();
+        open.total -= 1;
+        let remove = match open.by_address.get_mut(&self.key) {
+            Some(held) => {
+                *held -= 1;
+                *held == 0
+            }
+            None => false,
+        };
+        if remove {
+            open.by_address.remove(&self.key);
+        }
+    }
+}
+
+/// Formats received events as an SSE stream. Each event is followed by a
+/// `WouldBlock`, which makes rocket (with the `sse` feature) flush it.
+pub struct EventStream {
+    events: Receiver<PasteEvent>,
+    _slot: StreamSlot,
+    path: String,
+    pending: Vec<u8>,
+    position: usize,
+    flush: bool,
+    done: bool,
+}
+
+impl EventStream {
+    fn next_message(&mut self) -> Option<Vec<u8>> {
+        let event = match self.events.recv_timeout(HEARTBEAT) {
+            Ok(event) => event,
+            // Deletion by expiry doesn't go through the hub, so check the
+            // file whenever it's quiet.
+            Err(RecvTimeoutError::Timeout) if Path::new(&self.path).exists() => {
+                return Some(b": keep-alive\n\n".to_vec());
+            }
+            Err(RecvTimeoutError::Timeout) => PasteEvent::Deleted,
+            Err(RecvTimeoutError::Disconnected) => return None,
+        };
+        if let PasteEvent::Deleted = event {
+            self.done = true;
+        }
+        Some(event.to_sse())
+    }
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.pending.len() {
+            if self.flush {
+                self.flush = false;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if self.done {
+                return Ok(0);
+            }
+            match self.next_message() {
+                Some(message) => self.pending = message,
+                None => return Ok(0),
+            }
+            self.position = 0;
+            self.flush = true;
+        }
+        let n = (&self.pending[self.position..]).read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[derive(Responder)]
+enum Refused {
+    Locked(Locked),
+    Busy(Status),
+}
+
+#[get("/api/<id>/events")]
+fn events(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    password: Password,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    streams: State<Streams>,
+    hub: State<EventHub>,
+) -> Result<Option<Content<Stream<EventStream>>>, Refused> {
+    let path = format!("upload/{id}", id = id);
+    let meta = match PasteMeta::load_live(&id.to_string()) {
+        Some(meta) if Path::new(&path).exists() => meta,
+        _ => return Ok(None),
+    };
+    // Appended text would give away a protected paste.
+    passwords.unlock(&id.to_string(), &meta, &client, password.0.as_deref()).map_err(Refused::Locked)?;
+    let stream = EventStream {
+        _slot: streams.open(&client).map_err(Refused::Busy)?,
+        events: hub.subscribe(&id.to_string()),
+        path,
+        pending: b"retry: 3000\n\n".to_vec(),
+        position: 0,
+        flush: true,
+        done: false,
+    };
+    Ok(Some(Content(ContentType::new("text", "event-stream"), Stream::chunked(stream, 4096))))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![events]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("event streams", |rocket| {
+        let max = rocket.config().get_int("event_streams").unwrap_or(8).max(0) as usize;
+        let per_address = rocket.config().get_int("event_streams_per_address").unwrap_or(2).max(0) as usize;
+        Ok(rocket.manage(Streams { max, per_address, open: Arc::new(Mutex::new(OpenStreams::default())) }))
+    })
+}
@@ -0,0 +1,102 @@
+//! hastebin-compatible endpoints, so editor plugins and the `haste` CLI can
+//! use this server unchanged. Pastes are shared with `upload_api`.
+
+use rocket::data::Data;
+use rocket::response::status::NotFound;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use crate::api_key::Uploader;
+use crate::events::EventHub;
+use crate::meta::PasteMeta;
+use crate::options::UploadOptions;
+use crate::password::{Locked, Password, Passwords};
+use crate::paste_id::PasteID;
+use crate::rate_limit::{ClientIp, ReadLimit, UploadLimit};
+use crate::{open_paste, read_paste, store_paste, RawPaste, UploadError};
+
+#[derive(Serialize)]
+pub struct Key {
+    key: String,
+}
+
+#[derive(Serialize)]
+pub struct Document {
+    key: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+pub struct Message {
+    message: &'static str,
+}
+
+fn not_found() -> NotFound<Json<Message>> {
+    NotFound(Json(Message { message: "Document not found." }))
+}
+
+#[post("/documents", data = "<paste>")]
+fn create(
+    _limit: UploadLimit,
+    uploader: Uploader,
+    paste: Data,
+    options: Result<UploadOptions, String>,
+) -> Result<Json<Key>, UploadError> {
+    let mut options = options.map_err(UploadError::BadRequest)?;
+    uploader.attribute(&mut options);
+    let (id, _) = store_paste(paste.open(), &options)?;
+    Ok(Json(Key { key: id.to_string() }))
+}
+
+#[derive(Responder)]
+pub enum DocumentResponse {
+    Found(Json<Document>),
+    NotFound(NotFound<Json<Message>>),
+    Locked(Locked),
+}
+
+#[get("/documents/<id>")]
+fn document(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    password: Password,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    events: State<EventHub>,
+) -> DocumentResponse {
+    let key = id.to_string();
+    let meta = match PasteMeta::load_live(&key) {
+        Some(meta) => meta,
+        None => return DocumentResponse::NotFound(not_found()),
+    };
+    if let Err(locked) = passwords.unlock(&key, &meta, &client, password.0.as_deref()) {
+        return DocumentResponse::Locked(locked);
+    }
+    match read_paste(&key, meta, &events) {
+        Some((data, _)) => DocumentResponse::Found(Json(Document { key, data })),
+        None => DocumentResponse::NotFound(not_found()),
+    }
+}
+
+#[get("/raw/<id>")]
+fn raw(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    password: Password,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    events: State<EventHub>,
+) -> Result<Option<RawPaste>, Locked> {
+    let id = id.to_string();
+    let meta = match PasteMeta::load_live(&id) {
+        Some(meta) => meta,
+        None => return Ok(None),
+    };
+    passwords.unlock(&id, &meta, &client, password.0.as_deref())?;
+    Ok(open_paste(&id, meta, &events).map(|(file, meta)| RawPaste(file, meta)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, document, raw]
+}
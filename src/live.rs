@@ -0,0 +1,110 @@
+//! Live log sharing: the owner appends to a paste while viewers follow it.
+//!
+//!     make 2>&1 | while read -r line; do
+//!         echo "$line" | curl -H "X-Owner-Token: $TOKEN" --data-binary @- https://copy.red/api/<id>/append
+//!     done
+//!     curl https://copy.red/api/<id>?follow=1
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rocket::data::Data;
+use rocket::http::Status;
+use rocket::response::{Debug, Stream};
+use rocket::{Route, State};
+
+use crate::api_key::NotBanned;
+use crate::events::{EventHub, PasteEvent, StreamSlot};
+use crate::meta::{self, authorize, OwnerToken};
+use crate::moderation;
+use crate::paste_id::PasteID;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// A follower is disconnected once the paste hasn't grown for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const CHUNK_SIZE: u64 = 4096;
+
+/// Appended chunks are also published to event subscribers, so they are
+/// kept in memory and limited in size.
+const APPEND_LIMIT: u64 = 1 << 20;
+
+#[post("/api/<id>/append", data = "<data>")]
+fn append(_banned: NotBanned, id: PasteID<'_>, token: OwnerToken, data: Data, hub: State<EventHub>) -> Result<Status, Debug<io::Error>> {
+    let id = id.to_string();
+    match authorize(&id, &token) {
+        Err(status) => return Ok(status),
+        // Appending would break the envelope.
+        Ok(meta) if meta.encrypted => return Ok(Status::Conflict),
+        Ok(..) => {}
+    }
+    let mut chunk = Vec::new();
+    data.open().take(APPEND_LIMIT + 1).read_to_end(&mut chunk)?;
+    if chunk.len() as u64 > APPEND_LIMIT {
+        return Ok(Status::PayloadTooLarge);
+    }
+    let filename = format!("upload/{id}", id = id);
+    OpenOptions::new().append(true).open(&filename)?.write_all(&chunk)?;
+    // What counts is the whole paste; one that turns into banned content
+    // goes away entirely.
+    match moderation::check_content(Path::new(&filename)) {
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            meta::delete(&id);
+            hub.publish(&id, PasteEvent::Deleted);
+            return Ok(Status::Forbidden);
+        }
+        result => result?,
+    }
+    hub.publish(&id, PasteEvent::Appended { text: String::from_utf8_lossy(&chunk).into_owned() });
+    Ok(Status::NoContent)
+}
+
+/// Reads a paste like `tail -f`: at the end of the file it waits for more
+/// data until the paste is deleted or stays idle for `IDLE_TIMEOUT`.
+pub struct Tail {
+    file: File,
+    _slot: StreamSlot,
+    path: String,
+    last_growth: Instant,
+    unflushed: bool,
+}
+
+impl Tail {
+    pub fn new(id: &str, file: File, slot: StreamSlot) -> Tail {
+        Tail { file, _slot: slot, path: format!("upload/{id}", id = id), last_growth: Instant::now(), unflushed: false }
+    }
+}
+
+impl Read for Tail {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 {
+                self.last_growth = Instant::now();
+                self.unflushed = true;
+                return Ok(n);
+            }
+            // With rocket's `sse` feature, `WouldBlock` flushes what has
+            // been written so far instead of waiting for a full chunk.
+            if self.unflushed {
+                self.unflushed = false;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if !Path::new(&self.path).exists() || self.last_growth.elapsed() > IDLE_TIMEOUT {
+                return Ok(0);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// `slot` counts the stream against `event_streams` until it ends.
+pub fn follow(id: &str, file: File, slot: StreamSlot) -> Stream<Tail> {
+    Stream::chunked(Tail::new(id, file, slot), CHUNK_SIZE)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![append]
+}
@@ -1,18 +1,19 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 use maud::html;
 use maud::Markup;
+use maud::PreEscaped;
 
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate lazy_static;
 use rocket::{get, routes};
 use rocket::data::Data;
 use rocket::request::{self, Form, Request, FromRequest, FromParam};
-use rocket::response::{content::Plain, Debug, Redirect};
-use rocket::http::RawStr;
+use rocket::response::{self, content::Content, Debug, Redirect, Responder, Stream};
+use rocket::http::{uri::Uri, ContentType, RawStr, Status};
 use rocket::Outcome;
 use rocket::State;
 
-use std::io;
+use std::io::{self, Read, Write};
 use std::fs;
 use std::fmt;
 use std::fs::File;
@@ -22,8 +23,48 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 // use std::borrow::Cow;
 
+mod account;
+mod api_key;
+mod assets;
+mod channel;
+mod csrf;
+mod chat;
+mod edit;
+mod envelope;
+mod events;
+mod hastebin;
+mod live;
+mod meta;
+mod moderation;
+mod options;
+mod pairing;
+mod password;
 mod paste_id;
+mod pow;
+mod qr;
+mod rate_limit;
+mod report;
+mod security_headers;
+mod sprunge;
+mod termbin;
+mod tus;
+mod ws;
+use crate::account::{AccountError, User};
+use crate::api_key::Uploader;
+use crate::chat::Message;
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::events::{EventHub, PasteEvent, Streams};
+use crate::live::Tail;
+use crate::meta::{PasteMeta, Visibility};
+use crate::moderation::{AuditEntry, BanKind, PasteSummary};
+use crate::options::{UploadOptions, UPLOAD_OPTIONS};
+use crate::pairing::Pairings;
+use crate::password::{Locked, Password, Passwords};
 use crate::paste_id::PasteID;
+use crate::pow::{Challenges, ProofOfWork};
+use crate::rate_limit::{ClientIp, ReadLimit, UploadLimit};
+use crate::report::{Reason, ReportedPaste};
+use crate::security_headers::Nonce;
 
 #[cfg(test)] mod tests;
 
@@ -47,6 +88,85 @@ lazy_static!{
             ("info-h2", "脚本参考"),
             ("post-api-doc", "向网站提交任意数据, 返回带有<id>的网址, 等同于复制"),
             ("get-api-doc", "用<id>取回之前复制的内容, 等同于粘贴"),
+            ("options-api-doc", "上传选项, 以查询参数或请求头传入, 未知选项返回400"),
+            ("put-api-doc", "上传文件并保留文件名, 返回以文件名结尾的网址"),
+            ("pair-h1", "配对设备"),
+            ("pair-h2", "在一台设备获取6位配对码, 在另一台设备输入, 两台设备将共享同一个剪贴板频道"),
+            ("pair-request-button", "获取配对码"),
+            ("pair-code-placeholder", "输入配对码"),
+            ("pair-enter-button", "配对"),
+            ("pair-done", "已配对频道"),
+            ("pair-error-unknown", "配对码无效或已过期"),
+            ("pair-error-limit", "尝试次数过多, 请稍后再试"),
+            ("chat-no-channel", "先在首页配对设备, 剪贴板历史会显示在这里"),
+            ("chat-placeholder", "消息..."),
+            ("chat-copy", "点击复制"),
+            ("chat-h1", "讨论"),
+            ("chat-empty", "还没有消息, 可以针对某一行留言"),
+            ("chat-nick-placeholder", "昵称"),
+            ("chat-line-placeholder", "行"),
+            ("chat-send", "发送"),
+            ("chat-api-doc", "给粘贴留言, 可附带行号; GET 同一地址获取全部消息"),
+            ("qr-title", "用手机扫码打开"),
+            ("qr-api-doc", "粘贴链接的二维码 (SVG 或 PNG)"),
+            ("encrypt-label", "在浏览器中加密"),
+            ("encrypt-help", "服务器只保存密文, 密钥只在链接的 # 之后"),
+            ("encrypted-decrypting", "正在解密..."),
+            ("encrypted-error-key", "这是加密的粘贴, 链接中缺少密钥 (# 之后的部分)"),
+            ("encrypted-error-decrypt", "无法解密, 密钥可能不完整"),
+            ("encrypted-api-doc", "上传浏览器端加密的粘贴 (没有 API key 时需要表单的工作量证明), 正文必须是以下格式"),
+            ("password-optional", "密码 (可选)"),
+            ("password-h1", "此粘贴受密码保护"),
+            ("password-placeholder", "密码"),
+            ("password-button", "解锁"),
+            ("password-wrong", "密码错误"),
+            ("password-limit", "错误次数过多, 请一分钟后再试"),
+            ("password-api-doc", "上传时设置密码; 读取时用该请求头或 HTTP Basic 认证提供密码"),
+            ("api-key-doc", "用个人 API 密钥上传，粘贴会记在密钥名下，之后可用 delete 权限删除。管理员密钥可在 /api/keys 创建和吊销密钥。"),
+            ("login-h1", "登录"),
+            ("register-h1", "注册"),
+            ("account-name", "用户名"),
+            ("logout", "退出"),
+            ("my-pastes", "我的粘贴"),
+            ("my-pastes-search", "搜索"),
+            ("my-pastes-empty", "还没有粘贴。"),
+            ("delete", "删除"),
+            ("account-invalid-name", "用户名需 3 到 32 个小写字母、数字、- 或 _。"),
+            ("account-short-password", "密码至少 8 个字符。"),
+            ("account-name-taken", "该用户名已被使用。"),
+            ("account-wrong-password", "用户名或密码错误。"),
+            ("account-too-many-attempts", "尝试次数过多，请稍后再试。"),
+            ("account-registration-closed", "目前不开放注册。"),
+            ("admin-h1", "内容管理"),
+            ("admin-recent", "最近的粘贴"),
+            ("admin-size", "大小"),
+            ("admin-views", "浏览"),
+            ("admin-created", "创建时间"),
+            ("admin-ip", "IP 哈希"),
+            ("admin-quarantine", "隔离"),
+            ("admin-release", "解除隔离"),
+            ("admin-quarantined", "已隔离"),
+            ("admin-ban-ip", "封禁 IP"),
+            ("admin-ban-key", "封禁 API 密钥"),
+            ("admin-ban-content", "封禁内容"),
+            ("admin-bans", "封禁列表"),
+            ("admin-ban-value", "IP、IP 哈希、密钥 ID 或 SHA-256"),
+            ("admin-ban-button", "封禁"),
+            ("admin-unban", "解除"),
+            ("admin-audit", "审计日志"),
+            ("admin-encrypted", "加密的粘贴只能显示密文。"),
+            ("report", "举报"),
+            ("report-details", "补充说明（可选）"),
+            ("report-send", "提交举报"),
+            ("report-reason-spam", "垃圾信息"),
+            ("report-reason-malware", "恶意软件"),
+            ("report-reason-phishing", "钓鱼"),
+            ("report-reason-personal", "个人信息"),
+            ("report-reason-illegal", "违法内容"),
+            ("report-reason-other", "其他"),
+            ("admin-reports", "举报队列"),
+            ("admin-reports-empty", "没有待处理的举报。"),
+            ("follow", "关注更新"),
          ].iter().copied().collect()
         ),
         (ServerAcceptLangauge::Japananese,
@@ -64,6 +184,85 @@ lazy_static!{
             ("info-h2", "スクリプトの使用法"),
             ("get-api-doc", "ID` <id> `の貼り付けのコンテンツを取得します"),
             ("post-api-doc", "リクエストの本文の生データを受け入れ、本文のコンテンツを含むページのURLで応答します"),
+            ("options-api-doc", "アップロードオプション。クエリパラメータまたはヘッダーで指定します。不明なオプションは400になります"),
+            ("put-api-doc", "ファイル名を保持したままアップロードし、ファイル名で終わるURLを返します"),
+            ("pair-h1", "デバイスをペアリング"),
+            ("pair-h2", "一方のデバイスで6桁のコードを取得し、もう一方で入力すると、同じクリップボードチャンネルを共有します"),
+            ("pair-request-button", "コードを取得"),
+            ("pair-code-placeholder", "コードを入力"),
+            ("pair-enter-button", "ペアリング"),
+            ("pair-done", "ペアリング済みチャンネル"),
+            ("pair-error-unknown", "コードが無効か期限切れです"),
+            ("pair-error-limit", "試行回数が多すぎます。しばらくしてから再試行してください"),
+            ("chat-no-channel", "まずトップページでデバイスをペアリングすると、クリップボードの履歴がここに表示されます"),
+            ("chat-placeholder", "メッセージ..."),
+            ("chat-copy", "クリックしてコピー"),
+            ("chat-h1", "ディスカッション"),
+            ("chat-empty", "まだメッセージはありません。行番号を付けてコメントできます"),
+            ("chat-nick-placeholder", "ニックネーム"),
+            ("chat-line-placeholder", "行"),
+            ("chat-send", "送信"),
+            ("chat-api-doc", "ペーストにコメントします（行番号は任意）。同じURLへのGETで全メッセージを取得"),
+            ("qr-title", "スマートフォンでスキャンして開く"),
+            ("qr-api-doc", "ペーストURLのQRコード（SVGまたはPNG）"),
+            ("encrypt-label", "ブラウザで暗号化"),
+            ("encrypt-help", "サーバーには暗号文のみ保存され、鍵はリンクの # の後にだけあります"),
+            ("encrypted-decrypting", "復号しています..."),
+            ("encrypted-error-key", "暗号化されたペーストですが、リンクに鍵（# の後の部分）がありません"),
+            ("encrypted-error-decrypt", "復号できません。鍵が不完全かもしれません"),
+            ("encrypted-api-doc", "ブラウザで暗号化したペーストのアップロード (API キーがない場合はフォームのプルーフ・オブ・ワークが必要)。本文は次の形式である必要があります"),
+            ("password-optional", "パスワード（任意）"),
+            ("password-h1", "このペーストはパスワードで保護されています"),
+            ("password-placeholder", "パスワード"),
+            ("password-button", "開く"),
+            ("password-wrong", "パスワードが違います"),
+            ("password-limit", "失敗が多すぎます。1分後にもう一度お試しください"),
+            ("password-api-doc", "アップロード時にパスワードを設定し、読み取り時はこのヘッダーまたはHTTP Basic認証で渡します"),
+            ("api-key-doc", "個人の API キーでアップロードすると、ペーストはキーに紐付けられ、delete 権限で後から削除できます。管理者キーは /api/keys でキーの発行と失効ができます。"),
+            ("login-h1", "ログイン"),
+            ("register-h1", "登録"),
+            ("account-name", "ユーザー名"),
+            ("logout", "ログアウト"),
+            ("my-pastes", "マイペースト"),
+            ("my-pastes-search", "検索"),
+            ("my-pastes-empty", "ペーストはまだありません。"),
+            ("delete", "削除"),
+            ("account-invalid-name", "ユーザー名は小文字・数字・- ・_ で 3〜32 文字です。"),
+            ("account-short-password", "パスワードは 8 文字以上必要です。"),
+            ("account-name-taken", "このユーザー名は使われています。"),
+            ("account-wrong-password", "ユーザー名またはパスワードが違います。"),
+            ("account-too-many-attempts", "試行回数が多すぎます。しばらくしてから再試行してください。"),
+            ("account-registration-closed", "現在、登録は受け付けていません。"),
+            ("admin-h1", "モデレーション"),
+            ("admin-recent", "最近のペースト"),
+            ("admin-size", "サイズ"),
+            ("admin-views", "閲覧数"),
+            ("admin-created", "作成日時"),
+            ("admin-ip", "IP ハッシュ"),
+            ("admin-quarantine", "隔離"),
+            ("admin-release", "隔離解除"),
+            ("admin-quarantined", "隔離中"),
+            ("admin-ban-ip", "IP を禁止"),
+            ("admin-ban-key", "API キーを禁止"),
+            ("admin-ban-content", "内容を禁止"),
+            ("admin-bans", "禁止リスト"),
+            ("admin-ban-value", "IP、IP ハッシュ、キー ID または SHA-256"),
+            ("admin-ban-button", "禁止"),
+            ("admin-unban", "解除"),
+            ("admin-audit", "監査ログ"),
+            ("admin-encrypted", "暗号化されたペーストは暗号文のみ表示されます。"),
+            ("report", "通報"),
+            ("report-details", "詳細（任意）"),
+            ("report-send", "通報する"),
+            ("report-reason-spam", "スパム"),
+            ("report-reason-malware", "マルウェア"),
+            ("report-reason-phishing", "フィッシング"),
+            ("report-reason-personal", "個人情報"),
+            ("report-reason-illegal", "違法なコンテンツ"),
+            ("report-reason-other", "その他"),
+            ("admin-reports", "通報キュー"),
+            ("admin-reports-empty", "未処理の通報はありません。"),
+            ("follow", "更新をフォロー"),
          ].iter().copied().collect()
         ),
         (ServerAcceptLangauge::English,
@@ -81,6 +280,85 @@ lazy_static!{
             ("info-h2", "Script usage"),
             ("get-api-doc", "retrieves the content for the paste with id `<id>`"),
             ("post-api-doc", "accepts raw data in the body of the request and responds with a URL of a page containing the body's content "),
+            ("options-api-doc", "upload options, given as query parameters or headers; unknown options are rejected with 400"),
+            ("put-api-doc", "uploads a file keeping its name and responds with a URL ending in that name"),
+            ("pair-h1", "Pair a device"),
+            ("pair-h2", "Get a 6-digit code on one device and enter it on the other; both then share one clipboard channel."),
+            ("pair-request-button", "Get code"),
+            ("pair-code-placeholder", "Enter code"),
+            ("pair-enter-button", "Pair"),
+            ("pair-done", "Paired channel"),
+            ("pair-error-unknown", "Unknown or expired code"),
+            ("pair-error-limit", "Too many attempts, try again later"),
+            ("chat-no-channel", "Pair a device on the front page first; your clipboard history shows up here."),
+            ("chat-placeholder", "Message..."),
+            ("chat-copy", "Click to copy"),
+            ("chat-h1", "Discussion"),
+            ("chat-empty", "No messages yet. Comments can refer to a line of the paste."),
+            ("chat-nick-placeholder", "Nickname"),
+            ("chat-line-placeholder", "Line"),
+            ("chat-send", "Send"),
+            ("chat-api-doc", "Comment on a paste, optionally on a line; GET the same URL for all messages"),
+            ("qr-title", "Scan to open on your phone"),
+            ("qr-api-doc", "QR code of the paste URL, as SVG or PNG"),
+            ("encrypt-label", "Encrypt in the browser"),
+            ("encrypt-help", "The server only stores ciphertext; the key is only in the link after the #"),
+            ("encrypted-decrypting", "Decrypting..."),
+            ("encrypted-error-key", "This paste is encrypted and the link is missing its key (the part after #)."),
+            ("encrypted-error-decrypt", "Could not decrypt this paste; the key may be incomplete."),
+            ("encrypted-api-doc", "Upload an end-to-end encrypted paste (without an API key, solve the paste form's proof of work first); the body must be an envelope like"),
+            ("password-optional", "Password (optional)"),
+            ("password-h1", "This paste is password protected"),
+            ("password-placeholder", "Password"),
+            ("password-button", "Unlock"),
+            ("password-wrong", "Wrong password"),
+            ("password-limit", "Too many wrong attempts, try again in a minute"),
+            ("password-api-doc", "Set a password on upload; send it with this header or HTTP Basic auth to read the paste"),
+            ("api-key-doc", "Upload with a personal API key to have pastes attributed to it and deletable later with the delete scope. Admin keys mint and revoke keys at /api/keys."),
+            ("login-h1", "Log in"),
+            ("register-h1", "Register"),
+            ("account-name", "User name"),
+            ("logout", "Log out"),
+            ("my-pastes", "My pastes"),
+            ("my-pastes-search", "Search"),
+            ("my-pastes-empty", "No pastes yet."),
+            ("delete", "Delete"),
+            ("account-invalid-name", "User names are 3 to 32 lowercase letters, digits, - or _."),
+            ("account-short-password", "Passwords need at least 8 characters."),
+            ("account-name-taken", "That user name is taken."),
+            ("account-wrong-password", "Wrong user name or password."),
+            ("account-too-many-attempts", "Too many attempts, try again later."),
+            ("account-registration-closed", "Registration is closed."),
+            ("admin-h1", "Moderation"),
+            ("admin-recent", "Recent pastes"),
+            ("admin-size", "Size"),
+            ("admin-views", "Views"),
+            ("admin-created", "Created"),
+            ("admin-ip", "IP hash"),
+            ("admin-quarantine", "Quarantine"),
+            ("admin-release", "Release"),
+            ("admin-quarantined", "quarantined"),
+            ("admin-ban-ip", "Ban IP"),
+            ("admin-ban-key", "Ban API key"),
+            ("admin-ban-content", "Ban content"),
+            ("admin-bans", "Bans"),
+            ("admin-ban-value", "IP, IP hash, key ID or SHA-256"),
+            ("admin-ban-button", "Ban"),
+            ("admin-unban", "Lift"),
+            ("admin-audit", "Audit log"),
+            ("admin-encrypted", "Encrypted pastes can only be previewed as ciphertext."),
+            ("report", "Report"),
+            ("report-details", "Details (optional)"),
+            ("report-send", "Send report"),
+            ("report-reason-spam", "Spam"),
+            ("report-reason-malware", "Malware"),
+            ("report-reason-phishing", "Phishing"),
+            ("report-reason-personal", "Personal data"),
+            ("report-reason-illegal", "Illegal content"),
+            ("report-reason-other", "Other"),
+            ("admin-reports", "Reported pastes"),
+            ("admin-reports-empty", "No open reports."),
+            ("follow", "Follow changes"),
          ].iter().copied().collect()
         ),
     ].iter().cloned().collect();
@@ -137,53 +415,356 @@ impl fmt::Display for ServerAcceptLangauge {
 const HOST: &str = "https://copy.red";
 const ID_LENGTH: usize = 3;
 
+#[derive(Debug, Responder)]
+enum UploadError {
+    #[response(status = 400)]
+    BadRequest(String),
+    #[response(status = 403)]
+    Forbidden(String),
+    Io(Debug<io::Error>),
+}
+/// `InvalidData` errors describe a bad upload, e.g. a malformed envelope;
+/// `PermissionDenied` ones banned content.
+impl From<io::Error> for UploadError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::InvalidData => UploadError::BadRequest(error.to_string()),
+            io::ErrorKind::PermissionDenied => UploadError::Forbidden(error.to_string()),
+            _ => UploadError::Io(Debug(error)),
+        }
+    }
+}
+
+/// Response to an upload: the paste URL, plus the token needed to modify it.
+struct Created {
+    url: String,
+    token: String,
+}
+
+impl<'r> Responder<'r> for Created {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.url.respond_to(request)?;
+        response.set_raw_header("X-Owner-Token", self.token);
+        Ok(response)
+    }
+}
+
+/// Writes a new paste and returns its ID and owner token. Encrypted pastes
+/// are checked with `envelope::read` first. The paste only becomes readable
+/// after its content is checked and its meta saved.
+fn store_paste<R: Read>(mut paste: R, options: &UploadOptions) -> io::Result<(PasteID<'static>, String)> {
+    let (id, mut file) = meta::claim_id(options.id_length)?;
+    let partial = meta::partial_path(&id.to_string());
+    let written = match options.meta.encrypted {
+        true => envelope::read(paste).and_then(|envelope| file.write_all(&envelope)),
+        false => io::copy(&mut paste, &mut file).map(|_| ()),
+    };
+    if let Err(error) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(error);
+    }
+    moderation::check_content(Path::new(&partial))?;
+    let token = meta::new_token();
+    let meta = PasteMeta { owner_token: Some(token.clone()), created: Some(meta::unix_now()), ..options.meta.clone() };
+    meta.save(&id.to_string())?;
+    meta::publish(&id.to_string())?;
+    Ok((id, token))
+}
+
+/// Encrypted pastes come from the paste form's script, so without an API key
+/// they need the form's proof of work too, and like the form's pastes they
+/// are listed under the logged-in user.
 #[post("/api/paste", data = "<paste>")]
-fn upload_api(paste: Data) -> Result<String, Debug<io::Error>> {
-    let id = PasteID::new(ID_LENGTH);
-    let filename = format!("upload/{id}", id = id);
-    let url = format!("{host}/api/{id}\n", host = HOST, id = id);
-    paste.stream_to_file(Path::new(&filename))?;
-    Ok(url)
+fn upload_api(
+    _limit: UploadLimit,
+    uploader: Uploader,
+    user: Option<User>,
+    pow: Option<ProofOfWork>,
+    paste: Data,
+    options: Result<UploadOptions, String>,
+) -> Result<Created, UploadError> {
+    let mut options = options.map_err(UploadError::BadRequest)?;
+    if options.meta.encrypted && pow.is_none() {
+        return Err(UploadError::Forbidden("encrypted pastes need an API key or a solved `pow` challenge\n".into()));
+    }
+    uploader.attribute(&mut options);
+    options.meta.user = user.as_ref().map(|user| user.name.clone());
+    let (id, token) = store_paste(paste.open(), &options)?;
+    if let Some(user) = user {
+        account::record_paste(&user.name, &id.to_string())?;
+    }
+    Ok(Created { url: format!("{host}/api/{id}\n", host = HOST, id = id), token })
+}
+
+/// Routes below `/api/<id>/` that would shadow a file name in the URL.
+const RESERVED_FILENAMES: &[&str] = &["append", "chat", "events", "qr.png", "qr.svg"];
+
+/// transfer.sh style upload: `curl -T file.log https://copy.red/`
+#[put("/<filename>", data = "<paste>")]
+fn upload_put(
+    _limit: UploadLimit,
+    uploader: Uploader,
+    filename: &RawStr,
+    paste: Data,
+    options: Result<UploadOptions, String>,
+) -> Result<Created, UploadError> {
+    let mut options = options.map_err(UploadError::BadRequest)?;
+    uploader.attribute(&mut options);
+    let filename = filename.percent_decode()
+        .map_err(|_| UploadError::BadRequest("file name is not valid UTF-8\n".into()))?;
+    options.set("filename", &filename).map_err(UploadError::BadRequest)?;
+    let (id, token) = store_paste(paste.open(), &options)?;
+    let url = match RESERVED_FILENAMES.contains(&&*filename) {
+        true => format!("{host}/api/{id}\n", host = HOST, id = id),
+        false => format!("{host}/api/{id}/{filename}\n", host = HOST, id = id, filename = Uri::percent_encode(&filename)),
+    };
+    Ok(Created { url, token })
 }
 
 #[derive(Debug, FromForm)]
 struct PasteForm {
     paste_text: String,
+    /// Set when the page's script couldn't encrypt before submitting.
+    encrypt: bool,
+    password: Option<String>,
 }
-#[post("/", data = "<task>")]
-fn upload(lang: ServerAcceptLangauge, task: Form<PasteForm>) -> Result<Redirect, Debug<io::Error>> {
-    let id = PasteID::new(ID_LENGTH);
-    let filename = format!("upload/{id}", id = id);
-    fs::write(Path::new(&filename), &task.paste_text)?;
+/// Multipart posts to `/` are `sprunge::upload_multipart`'s.
+#[post("/", format = "application/x-www-form-urlencoded", data = "<task>")]
+fn upload(
+    _limit: UploadLimit,
+    uploader: Uploader,
+    user: Option<User>,
+    _pow: ProofOfWork,
+    lang: ServerAcceptLangauge,
+    task: CsrfForm<PasteForm>,
+) -> Result<Redirect, UploadError> {
+    if task.encrypt {
+        return Err(UploadError::BadRequest("encrypting a paste needs JavaScript\n".into()));
+    }
+    let mut options = UploadOptions::default();
+    uploader.attribute(&mut options);
+    options.meta.user = user.as_ref().map(|user| user.name.clone());
+    if let Some(password) = task.password.as_ref().filter(|password| !password.is_empty()) {
+        options.set("password", password).map_err(UploadError::BadRequest)?;
+    }
+    let (id, _) = store_paste(task.paste_text.as_bytes(), &options)?;
+    if let Some(user) = user {
+        account::record_paste(&user.name, &id.to_string())?;
+    }
     Ok(Redirect::to(format!("/{id}", id = id)))
 }
 
-#[get("/api/<id>", rank=1)]
-fn retrieve_api(id: PasteID<'_>, hit_count: State<HitCount>) -> Option<Plain<File>> {
-    let filename = format!("upload/{id}", id = id);
-    File::open(&filename).map(|f| Plain(f)).ok()
+/// Content types a browser would execute on our origin; these are served as
+/// plain text instead.
+fn is_active_content(content_type: &ContentType) -> bool {
+    content_type.is_html() || content_type.is_xml() || content_type.is_javascript()
+        || content_type.sub() == "svg+xml" || content_type.sub() == "xhtml+xml"
 }
 
-#[get("/<id>")]
-fn retrieve(id: PasteID<'_>, lang: ServerAcceptLangauge) -> Option<Markup> {
-    let url = format!("{host}/{id}\n", host = HOST, id = id);
+/// Picks the content type from the extension of the paste's file name.
+fn paste_content_type(meta: &PasteMeta) -> ContentType {
+    meta.filename.as_ref()
+        .and_then(|filename| Path::new(filename).extension())
+        .and_then(|ext| ext.to_str())
+        .and_then(ContentType::from_extension)
+        .filter(|content_type| !is_active_content(content_type))
+        .unwrap_or(ContentType::Plain)
+}
+
+/// A raw paste, with its settings surfaced as response headers.
+struct RawPaste(File, PasteMeta);
+
+impl<'r> Responder<'r> for RawPaste {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let RawPaste(file, meta) = self;
+        let mut response = Content(paste_content_type(&meta), file).respond_to(request)?;
+        if let Some(filename) = &meta.filename {
+            response.set_raw_header("Content-Disposition", format!("inline; filename=\"{}\"", filename));
+        }
+        if let Some(title) = meta.title {
+            response.set_raw_header("X-Paste-Title", title);
+        }
+        if let Some(syntax) = meta.syntax {
+            response.set_raw_header("X-Paste-Syntax", syntax);
+        }
+        if meta.visibility == Visibility::Unlisted {
+            response.set_raw_header("X-Robots-Tag", "noindex");
+        }
+        if meta.encrypted {
+            response.set_raw_header("X-Paste-Encrypted", "true");
+        }
+        if meta.password_hash.is_some() {
+            response.set_raw_header("Cache-Control", "private, no-store");
+        }
+        // Opened directly, a paste still can't run anything on our origin.
+        response.set_raw_header("Content-Security-Policy", "sandbox; default-src 'none'");
+        Ok(response)
+    }
+}
+
+/// ix.io/sprunge style highlighting request: a bare `?<lang>` after the URL.
+struct SyntaxSuffix(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for SyntaxSuffix {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let syntax = request.uri().query()
+            .filter(|query| !query.is_empty() && query.len() <= 32)
+            .filter(|query| query.chars().all(|c| c.is_ascii_alphanumeric() || "+#-_".contains(c)))
+            .map(|query| query.to_ascii_lowercase());
+        Outcome::Success(SyntaxSuffix(syntax))
+    }
+}
+
+/// Opens a paste for reading, honouring its expiry and burn-after-read;
+/// `meta` is what `PasteMeta::load_live` gave for it.
+fn open_paste(id: &str, mut meta: PasteMeta, events: &EventHub) -> Option<(File, PasteMeta)> {
     let filename = format!("upload/{id}", id = id);
-    match fs::read_to_string(&filename) {
-        Ok(f) => Some(default_view(Some(url), Some(f), lang)),
-        Err(..) => Some(default_view(None, None, lang))
+    let file = File::open(&filename).ok()?;
+    events.publish(id, PasteEvent::Viewed);
+    // The open handle keeps the contents readable after the unlink.
+    if meta.burn_after_read {
+        meta::delete(id);
+        events.publish(id, PasteEvent::Deleted);
+    } else if let Ok(views) = meta::count_view(id) {
+        meta.views = views;
     }
+    Some((file, meta))
 }
 
-#[get("/favicon.ico")]
-fn favicon() -> Option<Plain<File>> {
-    let filename = format!("static/icons/favicon.ico");
-    File::open(&filename).map(|f| Plain(f)).ok()
+fn read_paste(id: &str, meta: PasteMeta, events: &EventHub) -> Option<(String, PasteMeta)> {
+    let (mut file, meta) = open_paste(id, meta, events)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    Some((String::from_utf8_lossy(&bytes).into_owned(), meta))
 }
 
-#[get("/instantclick.min.js")]
-fn instantclick() -> Option<Plain<File>> {
-    let filename = format!("static/instantclick.min.js");
-    File::open(&filename).map(|f| Plain(f)).ok()
+#[derive(Responder)]
+enum PasteResponse {
+    Raw(RawPaste),
+    Follow(Stream<Tail>),
+    Locked(Locked),
+    Busy(Status),
+}
+
+/// `?follow=1` keeps the connection open and streams appended data.
+#[get("/api/<id>?<follow>", rank=1)]
+fn retrieve_api(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    follow: Option<&RawStr>,
+    password: Password,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    streams: State<Streams>,
+    events: State<EventHub>,
+    hit_count: State<HitCount>,
+) -> Option<PasteResponse> {
+    let id = id.to_string();
+    let meta = PasteMeta::load_live(&id)?;
+    if let Err(locked) = passwords.unlock(&id, &meta, &client, password.0.as_deref()) {
+        return Some(PasteResponse::Locked(locked));
+    }
+    let slot = match follow.and_then(|follow| options::parse_bool(follow)) {
+        Some(true) => match streams.open(&client) {
+            Ok(slot) => Some(slot),
+            Err(status) => return Some(PasteResponse::Busy(status)),
+        },
+        _ => None,
+    };
+    let (file, meta) = open_paste(&id, meta, &events)?;
+    match slot {
+        Some(slot) => Some(PasteResponse::Follow(live::follow(&id, file, slot))),
+        None => Some(PasteResponse::Raw(RawPaste(file, meta))),
+    }
+}
+
+/// Same as `retrieve_api`; the trailing file name only makes URLs returned by
+/// `upload_put` end in the original name.
+#[get("/api/<id>/<_filename>?<follow>", rank=2)]
+fn retrieve_api_named(
+    limit: ReadLimit,
+    id: PasteID<'_>,
+    _filename: &RawStr,
+    follow: Option<&RawStr>,
+    password: Password,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    streams: State<Streams>,
+    events: State<EventHub>,
+    hit_count: State<HitCount>,
+) -> Option<PasteResponse> {
+    retrieve_api(limit, id, follow, password, client, passwords, streams, events, hit_count)
+}
+
+/// The paste page, or the password prompt of a protected paste; `None`,
+/// and so a 404, for IDs with no paste.
+fn show_paste(
+    id: &str,
+    syntax: Option<String>,
+    password: Option<&str>,
+    client: &ClientIp,
+    passwords: &Passwords,
+    events: &EventHub,
+    challenges: &Challenges,
+    csrf: &CsrfToken,
+    nonce: &Nonce,
+    lang: ServerAcceptLangauge,
+) -> Option<Markup> {
+    let meta = PasteMeta::load_live(id)?;
+    if let Err(locked) = passwords.unlock(id, &meta, client, password) {
+        return Some(password_page(id, locked, lang));
+    }
+    let url = format!("{host}/{id}\n", host = HOST, id = id);
+    let (f, mut meta) = read_paste(id, meta, events)?;
+    meta.syntax = syntax.or(meta.syntax);
+    // The discussion and live updates would need the password again.
+    let thread = match meta.password_hash {
+        Some(..) => None,
+        None => Some(chat::load(id)),
+    };
+    Some(default_view(Some(url), Some(f), Some(&meta), thread.as_deref(), &challenges.issue(), csrf, nonce, lang))
+}
+
+#[get("/<id>")]
+fn retrieve(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    syntax: SyntaxSuffix,
+    password: Password,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    events: State<EventHub>,
+    challenges: State<Challenges>,
+    csrf: CsrfToken,
+    nonce: Nonce,
+    lang: ServerAcceptLangauge,
+) -> Option<Markup> {
+    let password = password.0.as_deref();
+    show_paste(&id.to_string(), syntax.0, password, &client, &passwords, &events, &challenges, &csrf, &nonce, lang)
+}
+
+#[derive(FromForm)]
+struct UnlockForm {
+    password: String,
+}
+
+/// Submitted by `password_page`.
+#[post("/<id>/unlock", data = "<form>")]
+fn unlock(
+    _limit: ReadLimit,
+    id: PasteID<'_>,
+    form: Form<UnlockForm>,
+    client: ClientIp,
+    passwords: State<Passwords>,
+    events: State<EventHub>,
+    challenges: State<Challenges>,
+    csrf: CsrfToken,
+    nonce: Nonce,
+    lang: ServerAcceptLangauge,
+) -> Option<Markup> {
+    let password = Some(form.password.as_str());
+    show_paste(&id.to_string(), None, password, &client, &passwords, &events, &challenges, &csrf, &nonce, lang)
 }
 
 #[get("/robots.txt")]
@@ -195,9 +776,15 @@ fn robots() -> &'static str {
 }
 
 #[get("/")]
-fn index(lang:ServerAcceptLangauge, hit_count: State<HitCount>) -> Redirect {
+fn index(
+    lang: ServerAcceptLangauge,
+    hit_count: State<HitCount>,
+    challenges: State<Challenges>,
+    csrf: CsrfToken,
+    nonce: Nonce,
+) -> Markup {
     hit_count.0.fetch_add(1, Ordering::Relaxed);
-    default_view(url,file,lang)
+    default_view(None, None, None, None, &challenges.issue(), &csrf, &nonce, lang)
 }
 
 #[get("/hitcount")]
@@ -241,17 +828,33 @@ fn language_switch_view(url: &Option<String>, lang: &ServerAcceptLangauge) -> Ma
     }
 }
 
-fn paste_textarea_view(url: &Option<String>, file: Option<String>, lang: &ServerAcceptLangauge) -> Markup {
+fn paste_textarea_view(
+    url: &Option<String>,
+    file: Option<String>,
+    encrypted: bool,
+    challenge: &str,
+    csrf: &CsrfToken,
+    lang: &ServerAcceptLangauge,
+) -> Markup {
     html! {
         form action=(format!("/{}",lang)) method="post" id="pasteData"
+            data-challenge=(challenge)
         {
+          (csrf.field())
           div class=r"flex flex-col space-y-6 py-6 bg-white shadow-xl border-2 border-dashed border-gray-200"
           {
               textarea class=r"border-4 border-red-300 border-opacity-75 h-32
                                focus:border-red-500 hover:border-red-500 p-5"
                   placeholder="Paste your text here"
-                  form="pasteData" name="paste_text"
+                  form="pasteData" name="paste_text" data-encrypted=(encrypted)
               { ( file.unwrap_or("".into()) ) }
+              input type="password" name="password" autocomplete="new-password"
+                  class="mx-5 px-2 py-1 border-2 border-gray-300"
+                  placeholder=(TEXT[&lang]["password-optional"]);
+              label class="flex items-center px-5 text-sm text-gray-600" title=(TEXT[&lang]["encrypt-help"]) {
+                input type="checkbox" name="encrypt" class="mr-2";
+                (TEXT[&lang]["encrypt-label"])
+              }
               button type="submit" form="pasteData"
               { (TEXT[&lang]["paste-button"]) }
           }
@@ -266,20 +869,7 @@ fn paste_textarea_view(url: &Option<String>, file: Option<String>, lang: &Server
                 br;
                 span id="copy2board" class="font-semibold mr-2 text-left flex-auto text-green-800"
                 { (url) }
-                i class="hover:text-teal-600 text-indigo-100" onclick=r#"
-                    (function copyToClipboard() {
-                      var aux = document.createElement("input");
-                      aux.setAttribute("value", document.getElementById("copy2board").innerHTML);
-                      document.body.appendChild(aux);
-                      aux.select();
-                      document.execCommand("copy");
-                      document.body.removeChild(aux);
-                      document.getElementById("copy2boardIcon").classList.add("animate-bounce");
-                      setTimeout(function(){
-                        document.getElementById("copy2boardIcon").classList.remove("animate-bounce");
-                      }, 300);
-                    })();
-                    "#
+                i id="copy2boardButton" class="hover:text-teal-600 text-indigo-100"
                 {
                   svg id="copy2boardIcon" class="h-8 w-8 "  fill="none" viewBox="0 0 24 24" stroke="currentColor"
                   {
@@ -289,13 +879,52 @@ fn paste_textarea_view(url: &Option<String>, file: Option<String>, lang: &Server
                              2 0 012 2v3m2 4H10m0 0l3-3m-3 3l3 3";
                   }
                 }
-            }}
+            }
+            // Without the key from the fragment the code would be useless.
+            @if let (false, Ok(svg)) = (encrypted, qr::inline_svg(url.trim_end())) {
+              div id="qrcode" class="flex justify-center mt-4" title=(TEXT[&lang]["qr-title"]) {
+                (PreEscaped(svg))
+              }
+            }
+            }
           },
           None => {}
         }
     }
 }
 
+/// Index page widget for `pairing.rs`. The resulting channel is remembered in
+/// `localStorage` (`channel`, `channelToken`) for the rest of the UI.
+fn pairing_view(lang: &ServerAcceptLangauge) -> Markup {
+    html! {
+        div id="pairing" class="my-2 px-4 py-5 bg-white shadow border-2 border-dashed border-gray-200"
+            data-error-unknown=(TEXT[&lang]["pair-error-unknown"])
+            data-error-limit=(TEXT[&lang]["pair-error-limit"])
+        {
+          h3 class="text-lg leading-6 font-medium text-gray-900"
+          { (TEXT[&lang]["pair-h1"]) }
+          p class="mt-1 text-sm leading-5 text-gray-500"
+          { (TEXT[&lang]["pair-h2"]) }
+          div class="flex items-center mt-2 space-x-4" {
+            button id="pair-request" type="button" class="px-2 py-1 border-2 border-red-300 hover:border-red-500"
+            { (TEXT[&lang]["pair-request-button"]) }
+            span id="pair-code" class="text-2xl font-mono tracking-widest" {}
+          }
+          form id="pair-form" class="flex items-center mt-2 space-x-4" {
+            input id="pair-entered" class="border-2 border-gray-300 px-2 py-1 font-mono"
+                inputmode="numeric" pattern="[0-9]{6}" maxlength="6"
+                placeholder=(TEXT[&lang]["pair-code-placeholder"]);
+            button type="submit" class="px-2 py-1 border-2 border-red-300 hover:border-red-500"
+            { (TEXT[&lang]["pair-enter-button"]) }
+          }
+          p id="pair-done" class="mt-2 text-sm text-green-800" hidden? {
+            (TEXT[&lang]["pair-done"]) " " code id="pair-channel" {}
+          }
+          p id="pair-error" class="mt-2 text-sm text-red-600" hidden? {}
+        }
+    }
+}
+
 fn description_view(lang: &ServerAcceptLangauge) -> Markup {
      html! {
         div class="bg-white shadow overflow-hidden sm:rounded-lg" {
@@ -329,30 +958,148 @@ fn description_view(lang: &ServerAcceptLangauge) -> Markup {
              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4"
              { (TEXT[&lang]["get-api-doc"]) br; "curl --data-binary @file.txt https://copy.red/api/paste" }
             }
+            div class="bg-white px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "POST /api/paste?<option>=<value>" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4" {
+                (TEXT[&lang]["options-api-doc"]) br;
+                "curl --data-binary @file.txt 'https://copy.red/api/paste?expiry=1h&burn'"
+                ul class="mt-2 list-disc list-inside" {
+                  @for (name, header, doc) in UPLOAD_OPTIONS {
+                    li { code { (name) } " / " code { (header) } ": " (doc) }
+                  }
+                }
+              }
+            }
+            div class="bg-gray-50 px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "PUT /<filename>" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4"
+              { (TEXT[&lang]["put-api-doc"]) br; "curl -T file.log https://copy.red/" }
+            }
             div class="bg-white px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
               dt class="text-sm leading-5 font-medium text-gray-500"
               { "GET /api/<id>" }
               dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4"
               { (TEXT[&lang]["get-api-doc"]) br; "curl https://copy.red/api/<id>" }
             }
+            div class="bg-gray-50 px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "POST /api/paste?encrypted" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4" {
+                (TEXT[&lang]["encrypted-api-doc"]) br;
+                code { r#"{"v": 1, "alg": "AES-GCM", "iv": "<base64>", "ct": "<base64>"}"# }
+              }
+            }
+            div class="bg-white px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "X-Paste-Password" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4" {
+                (TEXT[&lang]["password-api-doc"]) br;
+                "curl -H 'X-Paste-Password: hunter2' --data-binary @file.txt https://copy.red/api/paste" br;
+                "curl -u :hunter2 https://copy.red/api/<id>"
+              }
+            }
+            div class="bg-gray-50 px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "GET /api/<id>/qr.svg" br; "GET /api/<id>/qr.png" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4"
+              { (TEXT[&lang]["qr-api-doc"]) br; "curl -o qr.png https://copy.red/api/<id>/qr.png" }
+            }
+            div class="bg-white px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "POST /api/<id>/chat" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4" {
+                (TEXT[&lang]["chat-api-doc"]) br;
+                r#"curl -H 'Content-Type: application/json' -d '{"nick":"ann","line":12,"text":"off by one?"}' https://copy.red/api/<id>/chat"#
+              }
+            }
+            div class="bg-gray-50 px-4 py-5 sm:grid sm:grid-cols-5 sm:gap-4 sm:px-6" {
+              dt class="text-sm leading-5 font-medium text-gray-500"
+              { "Authorization: Bearer" }
+              dd class="mt-1 text-sm leading-5 text-gray-900 sm:mt-0 sm:col-span-4" {
+                (TEXT[&lang]["api-key-doc"]) br;
+                "curl -H 'Authorization: Bearer pb_<id>_<secret>' --data-binary @file.txt https://copy.red/api/paste" br;
+                r#"curl -H 'Authorization: Bearer <admin key>' -H 'Content-Type: application/json' -d '{"name":"ann","scopes":["upload","delete"]}' https://copy.red/api/keys"#
+              }
+            }
           }
         }}
      }
 }
 
-fn chatbox_view() -> Markup {
+/// The paste ID at the end of a paste page URL.
+fn url_paste_id(url: &Option<String>) -> Option<&str> {
+    url.as_ref().and_then(|url| url.trim_end().rsplit('/').next())
+}
+
+fn chat_message_view(message: Option<&Message>) -> Markup {
+    html! {
+      li class="bg-gray-300 w-3/4 mx-4 my-2 p-2 rounded-lg" {
+        div class="text-xs text-gray-600 space-x-2" {
+          span class="chat-nick font-bold" { (message.map_or("", |m| m.nick.as_str())) }
+          span class="chat-line font-mono" {
+            @if let Some(line) = message.and_then(|m| m.line) { "L" (line) }
+          }
+          span class="chat-time" {
+            @if let Some(message) = message {
+              (httpdate::fmt_http_date(std::time::UNIX_EPOCH + std::time::Duration::from_secs(message.time)))
+            }
+          }
+        }
+        p class="chat-text whitespace-pre-wrap break-words" { (message.map_or("", |m| m.text.as_str())) }
+      }
+    }
+}
+
+/// Discussion thread of a paste (see `chat.rs`). The form posts without
+/// JavaScript; once the page follows the paste, `events_script` adds
+/// messages from others as they arrive.
+fn chat_view(id: &str, thread: &[Message], csrf: &CsrfToken, lang: &ServerAcceptLangauge) -> Markup {
+    html! {
+      div id="chat" class="my-2 border-2 border-dashed" {
+        div class="w-full bg-green-400 h-12 pt-3 px-4 text-green-100 font-bold text-lg tracking-wide shadow-md"
+        { (TEXT[&lang]["chat-h1"]) }
+        ul id="chat-messages" class="mt-3 w-full max-h-64 overflow-y-auto" {
+          @if thread.is_empty() {
+            p id="chat-empty" class="mx-4 my-2 text-sm text-gray-500" { (TEXT[&lang]["chat-empty"]) }
+          }
+          @for message in thread {
+            (chat_message_view(Some(message)))
+          }
+        }
+        template { (chat_message_view(None)) }
+        form class="w-full flex flex-wrap bg-green-100" method="post" action=(format!("/api/{}/chat", id)) {
+          (csrf.field())
+          input class="m-2 mr-1 w-1/3 py-2 px-4 rounded border border-gray-300 bg-gray-200" name="nick"
+              maxlength="32" placeholder=(TEXT[&lang]["chat-nick-placeholder"]);
+          input class="m-2 ml-1 w-20 py-2 px-4 rounded border border-gray-300 bg-gray-200" name="line"
+              type="number" min="1" placeholder=(TEXT[&lang]["chat-line-placeholder"]);
+          textarea class="flex-grow m-2 mr-1 py-2 px-4 rounded border border-gray-300 bg-gray-200" rows="2"
+              name="text" required? maxlength="2000" placeholder=(TEXT[&lang]["chat-placeholder"])
+          {}
+          button type="submit" class="m-2 px-4 text-green-800 font-semibold"
+          { (TEXT[&lang]["chat-send"]) }
+        }
+      }
+    }
+}
+
+/// Clipboard history of the paired channel (see `pairing_view`), kept live
+/// over the WebSocket listener in `ws.rs`.
+fn chatbox_view(lang: &ServerAcceptLangauge) -> Markup {
     html!{
-      div id="chatbox" class="my-2 border-2 border-dashed" {
+      div id="chatbox" class="my-2 border-2 border-dashed" data-copy-title=(TEXT[&lang]["chat-copy"]) {
        div class="w-full bg-green-400 h-16 pt-2 text-white flex justify-between shadow-md" {
-          div to="/register_ws" {
+          a href="/#pairing" title=(TEXT[&lang]["pair-h1"]) {
             svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" class="w-12 h-12 my-1 text-green-100 ml-2"
             {
               path class="text-green-100 fill-current" d="M9.41 11H17a1 1 0 0 1 0 2H9.41l2.3 2.3a1 1 0 1 1-1.42 1.4l-4-4a1 1 0 0 1 0-1.4l4-4a1 1 0 0 1 1.42 1.4L9.4 11z"
               {}
             }
           }
-          div class="my-3 text-green-100 font-bold text-lg tracking-wide"
-          { "@rallip" }
+          div id="chatbox-channel" class="my-3 text-green-100 font-bold text-lg tracking-wide"
+          {}
           svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" class="icon-dots-vertical w-8 h-8 mt-2 mr-2"
           {
             path class="text-green-100 fill-current" fill-rule="evenodd"
@@ -361,22 +1108,17 @@ fn chatbox_view() -> Markup {
           }
        }
 
-       div class="mt-3 mb-16 w-full overflow-y-auto" {
-            div class="bg-gray-300 w-3/4 mx-4 my-2 p-2 rounded-lg h-16"
-              { "this is a basic mobile chat layout, build with tailwind css" }
-            div class="bg-gray-300 w-3/4 mx-4 my-2 p-2 rounded-lg h-16"
-              { "this is a basic mobile chat layout, build with tailwind css" }
-            div class="bg-green-300 float-right w-3/4 mx-4 my-2 p-2 rounded-lg clearfix h-16" {
-                "check my twitter to see when it will be released."
-            }
+       div id="chatbox-entries" class="mt-3 mb-16 w-full max-h-64 overflow-y-auto" {
+            p id="chatbox-no-channel" class="mx-4 my-2 text-sm text-gray-500"
+              { (TEXT[&lang]["chat-no-channel"]) }
        }
 
        div class="w-full flex bg-green-100 justify-between self-end" {
          textarea
              class="flex-grow m-2 w-5/7 py-2 px-4 mr-1 rounded border border-gray-300 bg-gray-200" rows="1"
-             placeholder="Message..."
+             id="chatbox-draft" placeholder=(TEXT[&lang]["chat-placeholder"])
            {}
-         button class="focus:shadow-outline" {
+         button id="chatbox-send" class="focus:shadow-outline" {
            svg class="svg-inline--fa text-green-400 fa-paper-plane fa-w-16 w-12 h-12 py-2 mr-2" aria-hidden="true"
                focusable="false" data-prefix="fas" data-icon="paper-plane"
                role="img" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 512 512"
@@ -393,6 +1135,27 @@ fn chatbox_view() -> Markup {
     }
 }
 
+/// The report form of a paste page; see `report.rs`.
+fn report_view(id: &str, csrf: &CsrfToken, lang: &ServerAcceptLangauge) -> Markup {
+    html! {
+      details id="report" class="my-2 text-sm text-gray-600" {
+        summary class="cursor-pointer" { (TEXT[&lang]["report"]) }
+        form action=(format!("/api/{}/report", id)) method="post" class="flex flex-col space-y-2 mt-2" {
+          (csrf.field())
+          select name="reason" class="border-2 border-gray-300 px-2 py-1" {
+            @for reason in &Reason::ALL {
+              option value=(reason.name()) { (TEXT[&lang][reason.text_key()]) }
+            }
+          }
+          textarea name="details" rows="2" maxlength="1000" class="border-2 border-gray-300 px-2 py-1"
+              placeholder=(TEXT[&lang]["report-details"])
+          {}
+          button type="submit" class="self-start text-red-600" { (TEXT[&lang]["report-send"]) }
+        }
+      }
+    }
+}
+
 fn footer_view() -> Markup {
     html!{
         div class="flex justify-center" {
@@ -401,7 +1164,6 @@ fn footer_view() -> Markup {
           svg class="h-12 w-12 p-2 mt-2"
               xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"
               aria-hidden="true" focusable="false" width="1em" height="1em"
-              style="-ms-transform: rotate(360deg); -webkit-transform: rotate(360deg); transform: rotate(360deg);"
               preserveAspectRatio="xMidYMid meet" viewBox="0 0 64 64" {
                   path fill="#626262" d=r"M32 0C14 0 0 14 0 32c0 21 19 30 22 30c2
                       0 2-1 2-2v-5c-7 2-10-2-11-5c0 0 0-1-2-3c-1-1-5-3-1-3c3 0
@@ -415,57 +1177,794 @@ fn footer_view() -> Markup {
     }
 }
 
-fn default_view(url: Option<String>, file: Option<String>, lang: ServerAcceptLangauge) -> Markup {
+/// Placeholder for an encrypted paste, replaced by its plain text once
+/// `encryption_script` has decrypted the envelope with the key from the URL.
+fn encrypted_view(envelope: &str, lang: &ServerAcceptLangauge) -> Markup {
+    html! {
+        p id="encrypted" class="my-2 p-2 text-sm text-gray-600 border-2 border-dashed border-gray-200"
+            data-envelope=(envelope)
+            data-error-key=(TEXT[&lang]["encrypted-error-key"])
+            data-error-decrypt=(TEXT[&lang]["encrypted-error-decrypt"])
+        { (TEXT[&lang]["encrypted-decrypting"]) }
+    }
+}
+
+/// Client side of `envelope.rs`: encrypts the textarea on submit when asked
+/// to, and decrypts the paste on its page. Keys travel in the URL fragment
+/// as unpadded base64url.
+fn encryption_script(nonce: &Nonce) -> Markup {
+    html! {
+      script nonce=(nonce.0) {
+        (PreEscaped(r#"
+          function toBase64(buffer) {
+            var bytes = new Uint8Array(buffer), text = '';
+            for (var i = 0; i < bytes.length; i++) { text += String.fromCharCode(bytes[i]); }
+            return btoa(text);
+          }
+          function fromBase64(text) {
+            var binary = atob(text.replace(/-/g, '+').replace(/_/g, '/'));
+            var bytes = new Uint8Array(binary.length);
+            for (var i = 0; i < binary.length; i++) { bytes[i] = binary.charCodeAt(i); }
+            return bytes;
+          }
+          function decryptEnvelope(text) {
+            var envelope = JSON.parse(text);
+            var key = location.hash.slice(1);
+            if (!key) { return Promise.reject('key'); }
+            return crypto.subtle.importKey('raw', fromBase64(key), 'AES-GCM', false, ['decrypt'])
+              .then(function (key) {
+                return crypto.subtle.decrypt({ name: 'AES-GCM', iv: fromBase64(envelope.iv) }, key, fromBase64(envelope.ct));
+              })
+              .then(function (plain) { return new TextDecoder().decode(plain); });
+          }
+          function encryptedUpload(event) {
+            var form = event.target;
+            if (!form.encrypt.checked) { return; }
+            event.preventDefault();
+            var iv = crypto.getRandomValues(new Uint8Array(12)), key;
+            crypto.subtle.generateKey({ name: 'AES-GCM', length: 256 }, true, ['encrypt'])
+              .then(function (generated) {
+                key = generated;
+                var plain = new TextEncoder().encode(form.paste_text.value);
+                return crypto.subtle.encrypt({ name: 'AES-GCM', iv: iv }, key, plain);
+              })
+              .then(function (ct) {
+                var envelope = JSON.stringify({ v: 1, alg: 'AES-GCM', iv: toBase64(iv), ct: toBase64(ct) });
+                return solveChallenge(form).then(function (solution) {
+                  var url = '/api/paste?encrypted' + (solution ? '&pow=' + encodeURIComponent(solution) : '');
+                  var headers = form.password.value ? { 'X-Paste-Password': form.password.value } : {};
+                  return fetch(url, { method: 'POST', headers: headers, body: envelope });
+                });
+              })
+              .then(function (r) { if (!r.ok) { throw r.status; } return r.text(); })
+              .then(function (url) {
+                return crypto.subtle.exportKey('raw', key).then(function (raw) {
+                  var fragment = toBase64(raw).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+                  location.href = '/' + url.trim().split('/').pop() + '#' + fragment;
+                });
+              })
+              .catch(function (error) {
+                form.querySelector('button[type=submit]').disabled = false;
+                alert(error);
+              });
+          }
+          (function () {
+            var viewer = document.getElementById('encrypted');
+            if (!viewer) { return; }
+            var link = document.getElementById('copy2board');
+            link.textContent = link.textContent.trim() + location.hash;
+            decryptEnvelope(viewer.dataset.envelope)
+              .then(function (text) {
+                document.querySelector('#pasteData textarea').value = text;
+                viewer.remove();
+              })
+              .catch(function (error) {
+                viewer.textContent = error === 'key' ? viewer.dataset.errorKey : viewer.dataset.errorDecrypt;
+              });
+          })();
+        "#))
+      }
+    }
+}
+
+/// Solves the challenge of form `form_id` (see `pow.rs`) before it is
+/// submitted. On pages that can encrypt, `encryptedUpload` uses the same
+/// `solveChallenge` for its own request.
+fn proof_of_work_script(form_id: &str, nonce: &Nonce) -> Markup {
+    html! {
+      script nonce=(nonce.0) {
+        (PreEscaped(r#"
+          function solveChallenge(form) {
+            var challenge = form.dataset.challenge;
+            if (!challenge) { return Promise.resolve(null); }
+            form.querySelector('button[type=submit]').disabled = true;
+            var bits = parseInt(challenge, 10), encoder = new TextEncoder();
+            function zeroBits(hash) {
+              var bytes = new Uint8Array(hash), n = 0;
+              for (var i = 0; i < bytes.length; i++) {
+                if (bytes[i] !== 0) { return n + Math.clz32(bytes[i]) - 24; }
+                n += 8;
+              }
+              return n;
+            }
+            function attempt(start) {
+              var tries = [];
+              for (var i = 0; i < 512; i++) {
+                tries.push(crypto.subtle.digest('SHA-256', encoder.encode(challenge + '.' + (start + i))));
+              }
+              return Promise.all(tries).then(function (hashes) {
+                for (var i = 0; i < hashes.length; i++) {
+                  if (zeroBits(hashes[i]) >= bits) { return start + i; }
+                }
+                return attempt(start + hashes.length);
+              });
+            }
+            return attempt(0).then(function (counter) { return challenge + '.' + counter; });
+          }
+          function proofOfWork(event) {
+            var form = event.target;
+            if (event.defaultPrevented || !form.dataset.challenge) { return; }
+            event.preventDefault();
+            solveChallenge(form).then(function (solution) {
+              var action = form.getAttribute('action');
+              form.action = action + (action.indexOf('?') < 0 ? '?' : '&') + 'pow=' + encodeURIComponent(solution);
+              form.submit();
+            });
+          }
+        "#))
+        (PreEscaped(format!("document.getElementById('{}')", form_id)))
+        (PreEscaped(r#".addEventListener('submit', function (event) {
+            if (typeof encryptedUpload === 'function') { encryptedUpload(event); }
+            proofOfWork(event);
+          });
+        "#))
+      }
+    }
+}
+
+fn highlighted_view(file: &Option<String>, meta: Option<&PasteMeta>) -> Markup {
+    html! {
+        @if let (Some(file), Some(syntax)) = (file, meta.and_then(|meta| meta.syntax.as_ref())) {
+          pre class="my-2 overflow-x-auto text-sm border-2 border-dashed border-gray-200" {
+            code class=(format!("language-{}", syntax)) { (file) }
+          }
+        }
+    }
+}
+
+/// Keeps an open paste page in sync with `/api/<id>/events`: appended text
+/// is added (scrolling to the end), edits reload and deletion clears it.
+/// A stream holds a worker, so it is only opened by the follow button or
+/// when the page is opened with `?follow=1`.
+fn events_script(url: &Option<String>, nonce: &Nonce) -> Markup {
+    let id = match url_paste_id(url) {
+        Some(id) => id,
+        None => return html! {},
+    };
+    html! {
+      script nonce=(nonce.0) {
+        (PreEscaped(format!(r#"
+          (function () {{
+            var box = document.querySelector('#pasteData textarea');
+            var button = document.getElementById('follow');
+            var source = null;
+            function show(text) {{
+              box.value = text;
+              box.scrollTop = box.scrollHeight;
+            }}
+            function follow() {{
+              if (source) {{ return; }}
+              source = new EventSource('/api/{id}/events');
+              button.disabled = true;
+              source.addEventListener('appended', function (e) {{
+                show(box.value + JSON.parse(e.data).text);
+              }});
+              source.addEventListener('edited', function () {{
+                fetch('/api/{id}')
+                  .then(function (r) {{ return r.text(); }})
+                  .then(function (text) {{ return box.dataset.encrypted === 'true' ? decryptEnvelope(text) : text; }})
+                  .then(show);
+              }});
+              source.addEventListener('chat', function (e) {{
+                var message = JSON.parse(e.data);
+                var item = document.querySelector('#chat template').content.cloneNode(true);
+                item.querySelector('.chat-nick').textContent = message.nick;
+                item.querySelector('.chat-line').textContent = message.line ? 'L' + message.line : '';
+                item.querySelector('.chat-time').textContent = new Date(message.time * 1000).toUTCString();
+                item.querySelector('.chat-text').textContent = message.text;
+                document.getElementById('chat-messages').appendChild(item);
+                var empty = document.getElementById('chat-empty');
+                if (empty) {{ empty.remove(); }}
+              }});
+              source.addEventListener('deleted', function () {{
+                source.close();
+                show('');
+              }});
+            }}
+            button.addEventListener('click', follow);
+            if (new URLSearchParams(location.search).get('follow') === '1') {{ follow(); }}
+          }})();
+        "#, id = id)))
+      }
+    }
+}
+
+fn default_view(
+    url: Option<String>,
+    file: Option<String>,
+    meta: Option<&PasteMeta>,
+    thread: Option<&[Message]>,
+    challenge: &str,
+    csrf: &CsrfToken,
+    nonce: &Nonce,
+    lang: ServerAcceptLangauge,
+) -> Markup {
+  let syntax = meta.map_or(false, |meta| meta.syntax.is_some());
+  let protected = meta.map_or(false, |meta| meta.password_hash.is_some());
+  // An encrypted paste is only handed to `encrypted_view`.
+  let encrypted = meta.map_or(false, |meta| meta.encrypted);
+  let (file, envelope) = match encrypted {
+      true => (None, file),
+      false => (file, None),
+  };
   html! {
     head {
         meta charset="utf-8" {}
         meta name="viewport" content="width=device-width, initial-scale=1, maximum-scale=1" {}
-        link href="https://unpkg.com/tailwindcss@^1.0/dist/tailwind.min.css" rel="stylesheet" {}
-        script src="https://cdn.jsdelivr.net/gh/alpinejs/alpine@v2.x.x/dist/alpine.min.js" defer? {}
-        title { (TEXT[&lang]["site-title"]) }
+        link href=(assets::url("vendor/tailwind.min.css")) rel="stylesheet" {}
+        link rel="icon" href=(assets::url("icons/favicon-32x32.png")) {}
+        @if syntax {
+          link href=(assets::url("vendor/highlight.min.css")) rel="stylesheet" {}
+          script src=(assets::url("vendor/highlight.min.js")) nonce=(nonce.0) {}
+          script nonce=(nonce.0) { "hljs.initHighlightingOnLoad();" }
+        }
+        @match meta.and_then(|meta| meta.title.as_ref()) {
+          Some(paste_title) => title { (paste_title) " - " (TEXT[&lang]["site-title"]) },
+          None => title { (TEXT[&lang]["site-title"]) },
+        }
     }
     body {
       div class="min-h-screen flex items-center justify-center bg-gray-50 py-12 px-4 sm:px-6 lg:px-8" {
        div class="max-w-lg w-full" {
         (language_switch_view(&url,&lang))
-        (paste_textarea_view(&url,file, &lang))
-        (chatbox_view())
+        a href="/my" class="float-right -mt-6 text-sm text-gray-600" { (TEXT[&lang]["my-pastes"]) }
+        (highlighted_view(&file, meta))
+        (paste_textarea_view(&url,file, encrypted, challenge, csrf, &lang))
+        @if let Some(envelope) = envelope {
+          (encrypted_view(&envelope, &lang))
+        }
+        @match (url_paste_id(&url), thread) {
+          (Some(id), Some(thread)) => (chat_view(id, thread, csrf, &lang)),
+          (Some(..), None) => {},
+          (None, _) => {
+            (pairing_view(&lang))
+            (chatbox_view(&lang))
+          }
+        }
+        @if let (Some(..), false) = (url_paste_id(&url), protected) {
+          button id="follow" type="button" class="my-2 text-sm text-gray-600" { (TEXT[&lang]["follow"]) }
+        }
+        @if let Some(id) = url_paste_id(&url) {
+          (report_view(id, csrf, &lang))
+        }
         (description_view(&lang))
         (footer_view())
        }
       }
-      script {
+      script src=(assets::url("js/clipboard.js")) {}
+      (encryption_script(nonce))
+      (proof_of_work_script("pasteData", nonce))
+      @if !protected {
+        (events_script(&url, nonce))
+      }
+      script nonce=(nonce.0) {
         r#"
           console.log('Send your Resume!');
         "#
       }
-      ( development_script_tag() )
+      ( development_script_tag(nonce) )
   }}
 }
 
+/// Prompt for the password of a protected paste; see `password.rs`.
+fn password_page(id: &str, locked: Locked, lang: ServerAcceptLangauge) -> Markup {
+  let error = match locked {
+      Locked::Required => None,
+      Locked::Wrong => Some(TEXT[&lang]["password-wrong"]),
+      Locked::TooManyAttempts(..) => Some(TEXT[&lang]["password-limit"]),
+  };
+  html! {
+    head {
+        meta charset="utf-8" {}
+        meta name="viewport" content="width=device-width, initial-scale=1, maximum-scale=1" {}
+        meta name="robots" content="noindex" {}
+        link href=(assets::url("vendor/tailwind.min.css")) rel="stylesheet" {}
+        title { (TEXT[&lang]["password-h1"]) " - " (TEXT[&lang]["site-title"]) }
+    }
+    body {
+      div class="min-h-screen flex items-center justify-center bg-gray-50 py-12 px-4 sm:px-6 lg:px-8" {
+       div class="max-w-lg w-full" {
+        form action=(format!("/{}/unlock", id)) method="post"
+            class="flex flex-col space-y-4 p-6 bg-white shadow-xl border-2 border-dashed border-gray-200"
+        {
+          h3 class="text-lg leading-6 font-medium text-gray-900" { (TEXT[&lang]["password-h1"]) }
+          input type="password" name="password" autofocus? required? autocomplete="current-password"
+              class="border-4 border-red-300 focus:border-red-500 p-2"
+              placeholder=(TEXT[&lang]["password-placeholder"]);
+          @if let Some(error) = error {
+            p class="text-sm text-red-600" { (error) }
+          }
+          button type="submit" { (TEXT[&lang]["password-button"]) }
+        }
+        (footer_view())
+       }
+      }
+    }
+  }
+}
+
+/// Shared by the login and registration pages; only registration comes with
+/// a proof of work, as `(challenge, nonce)`.
+fn account_form_page(
+    action: &str,
+    error: Option<AccountError>,
+    pow: Option<(&str, &Nonce)>,
+    csrf: &CsrfToken,
+    lang: &ServerAcceptLangauge,
+) -> Markup {
+  let heading = match action {
+      "login" => TEXT[&lang]["login-h1"],
+      _ => TEXT[&lang]["register-h1"],
+  };
+  html! {
+    head {
+        meta charset="utf-8" {}
+        meta name="viewport" content="width=device-width, initial-scale=1, maximum-scale=1" {}
+        meta name="robots" content="noindex" {}
+        link href=(assets::url("vendor/tailwind.min.css")) rel="stylesheet" {}
+        title { (heading) " - " (TEXT[&lang]["site-title"]) }
+    }
+    body {
+      div class="min-h-screen flex items-center justify-center bg-gray-50 py-12 px-4 sm:px-6 lg:px-8" {
+       div class="max-w-lg w-full" {
+        form action=(format!("/{}", action)) method="post" id="accountForm"
+            data-challenge=(pow.map_or("", |(challenge, _)| challenge))
+            class="flex flex-col space-y-4 p-6 bg-white shadow-xl border-2 border-dashed border-gray-200"
+        {
+          (csrf.field())
+          h3 class="text-lg leading-6 font-medium text-gray-900" { (heading) }
+          input type="text" name="name" autofocus? required? autocomplete="username"
+              class="border-4 border-red-300 focus:border-red-500 p-2"
+              placeholder=(TEXT[&lang]["account-name"]);
+          input type="password" name="password" required?
+              autocomplete=(if action == "login" { "current-password" } else { "new-password" })
+              class="border-4 border-red-300 focus:border-red-500 p-2"
+              placeholder=(TEXT[&lang]["password-placeholder"]);
+          @if let Some(error) = error {
+            p class="text-sm text-red-600" { (TEXT[&lang][error.text_key()]) }
+          }
+          button type="submit" { (heading) }
+          @if action == "login" {
+            a href="/register" class="text-sm text-gray-600" { (TEXT[&lang]["register-h1"]) }
+          } @else {
+            a href="/login" class="text-sm text-gray-600" { (TEXT[&lang]["login-h1"]) }
+          }
+        }
+        (footer_view())
+       }
+      }
+      @if let Some((_, nonce)) = pow {
+        (proof_of_work_script("accountForm", nonce))
+      }
+    }
+  }
+}
+
+fn login_page(error: Option<AccountError>, csrf: &CsrfToken, lang: ServerAcceptLangauge) -> Markup {
+    account_form_page("login", error, None, csrf, &lang)
+}
+
+fn register_page(
+    error: Option<AccountError>,
+    challenge: &str,
+    csrf: &CsrfToken,
+    nonce: &Nonce,
+    lang: ServerAcceptLangauge,
+) -> Markup {
+    account_form_page("register", error, Some((challenge, nonce)), csrf, &lang)
+}
+
+/// `/my`: the pastes of a logged-in user, with a search box.
+fn my_pastes_page(
+    name: &str,
+    pastes: &[(String, PasteMeta)],
+    query: Option<&str>,
+    csrf: &CsrfToken,
+    lang: ServerAcceptLangauge,
+) -> Markup {
+  html! {
+    head {
+        meta charset="utf-8" {}
+        meta name="viewport" content="width=device-width, initial-scale=1, maximum-scale=1" {}
+        meta name="robots" content="noindex" {}
+        link href=(assets::url("vendor/tailwind.min.css")) rel="stylesheet" {}
+        title { (TEXT[&lang]["my-pastes"]) " - " (TEXT[&lang]["site-title"]) }
+    }
+    body {
+      div class="min-h-screen flex items-center justify-center bg-gray-50 py-12 px-4 sm:px-6 lg:px-8" {
+       div class="max-w-lg w-full" {
+        div class="flex justify-between items-center mb-2 text-sm" {
+          a href="/" { (TEXT[&lang]["site-title"]) }
+          form action="/logout" method="post" {
+            (csrf.field())
+            span class="text-gray-600 mr-2" { (name) }
+            button type="submit" class="text-gray-600 underline" { (TEXT[&lang]["logout"]) }
+          }
+        }
+        div class="bg-white shadow-xl border-2 border-dashed border-gray-200 p-6" {
+          h3 class="text-lg leading-6 font-medium text-gray-900" { (TEXT[&lang]["my-pastes"]) }
+          form action="/my" method="get" class="flex my-4" {
+            input type="search" name="q" value=(query.unwrap_or(""))
+                class="flex-auto border-2 border-gray-300 px-2 py-1"
+                placeholder=(TEXT[&lang]["my-pastes-search"]);
+            button type="submit" class="ml-2" { (TEXT[&lang]["my-pastes-search"]) }
+          }
+          @if pastes.is_empty() {
+            p class="text-sm text-gray-600" { (TEXT[&lang]["my-pastes-empty"]) }
+          }
+          ul class="divide-y divide-gray-200" {
+            @for (id, meta) in pastes {
+              li class="flex items-center justify-between py-2 text-sm" {
+                a href=(format!("/{}", id)) class="font-mono" {
+                  (id)
+                  @if let Some(title) = meta.title.as_ref().or(meta.filename.as_ref()) {
+                    span class="ml-2 font-sans text-gray-600" { (title) }
+                  }
+                }
+                form action=(format!("/my/{}/delete", id)) method="post" {
+                  (csrf.field())
+                  button type="submit" class="text-red-600" { (TEXT[&lang]["delete"]) }
+                }
+              }
+            }
+          }
+        }
+        (footer_view())
+       }
+      }
+    }
+  }
+}
+
+fn admin_head(title: &str, lang: &ServerAcceptLangauge) -> Markup {
+  html! {
+    head {
+        meta charset="utf-8" {}
+        meta name="viewport" content="width=device-width, initial-scale=1, maximum-scale=1" {}
+        meta name="robots" content="noindex" {}
+        link href=(assets::url("vendor/tailwind.min.css")) rel="stylesheet" {}
+        title { (title) " - " (TEXT[&lang]["site-title"]) }
+    }
+  }
+}
+
+/// A one-button form posting to an `/admin` action.
+fn admin_action(csrf: &CsrfToken, path: &str, class: &str, label: &str) -> Markup {
+  html! {
+    form action=(path) method="post" class="inline" {
+      (csrf.field())
+      button type="submit" class=(class) { (label) }
+    }
+  }
+}
+
+fn admin_ban_button(csrf: &CsrfToken, kind: BanKind, value: &str, label: &str) -> Markup {
+  html! {
+    form action="/admin/bans" method="post" class="inline" {
+      (csrf.field())
+      input type="hidden" name="kind" value=(kind.name());
+      input type="hidden" name="value" value=(value);
+      button type="submit" class="text-red-600" { (label) }
+    }
+  }
+}
+
+/// The moderation panel; see `moderation.rs`.
+fn admin_page(
+    name: &str,
+    reported: &[ReportedPaste],
+    pastes: &[PasteSummary],
+    bans: &[(BanKind, String)],
+    audit: &[AuditEntry],
+    csrf: &CsrfToken,
+    lang: ServerAcceptLangauge,
+) -> Markup {
+  html! {
+    (admin_head(TEXT[&lang]["admin-h1"], &lang))
+    body {
+      div class="min-h-screen flex justify-center bg-gray-50 py-12 px-4 sm:px-6 lg:px-8" {
+       div class="max-w-5xl w-full space-y-6" {
+        div class="flex justify-between items-center text-sm" {
+          a href="/" { (TEXT[&lang]["site-title"]) }
+          form action="/logout" method="post" {
+            (csrf.field())
+            span class="text-gray-600 mr-2" { (name) }
+            button type="submit" class="text-gray-600 underline" { (TEXT[&lang]["logout"]) }
+          }
+        }
+        div class="bg-white shadow-xl border-2 border-dashed border-gray-200 p-6" {
+          h3 class="text-lg leading-6 font-medium text-gray-900 mb-4" { (TEXT[&lang]["admin-reports"]) }
+          @if reported.is_empty() {
+            p class="text-sm text-gray-600" { (TEXT[&lang]["admin-reports-empty"]) }
+          }
+          ul class="divide-y divide-gray-200 text-sm" {
+            @for paste in reported {
+              li class="py-2" {
+                div class="flex justify-between" {
+                  span {
+                    a href=(format!("/admin/{}", paste.id)) class="font-mono underline" { (paste.id) }
+                    span class="ml-2 text-gray-600" { "× " (paste.reports.len()) }
+                    @if paste.meta.quarantined {
+                      span class="ml-2 text-red-600" { (TEXT[&lang]["admin-quarantined"]) }
+                    }
+                  }
+                  span class="space-x-2" {
+                    (admin_action(csrf, &format!("/admin/{}/release", paste.id), "text-green-700", TEXT[&lang]["admin-release"]))
+                    (admin_action(csrf, &format!("/admin/{}/delete", paste.id), "text-red-600", TEXT[&lang]["delete"]))
+                  }
+                }
+                ul class="ml-4 text-xs text-gray-600" {
+                  @for report in &paste.reports {
+                    li {
+                      (TEXT[&lang][report.reason.text_key()])
+                      @if !report.details.is_empty() { ": " (report.details) }
+                    }
+                  }
+                }
+              }
+            }
+          }
+        }
+        div class="bg-white shadow-xl border-2 border-dashed border-gray-200 p-6 overflow-x-auto" {
+          h3 class="text-lg leading-6 font-medium text-gray-900 mb-4" { (TEXT[&lang]["admin-recent"]) }
+          table class="w-full text-sm text-left" {
+            tr class="text-gray-600" {
+              th { "ID" }
+              th { (TEXT[&lang]["admin-size"]) }
+              th { (TEXT[&lang]["admin-views"]) }
+              th { (TEXT[&lang]["admin-created"]) }
+              th { (TEXT[&lang]["admin-ip"]) }
+              th {}
+            }
+            @for paste in pastes {
+              tr class="border-t border-gray-200" {
+                td class="py-1" {
+                  a href=(format!("/admin/{}", paste.id)) class="font-mono underline" { (paste.id) }
+                  @if let Some(title) = paste.meta.title.as_ref().or(paste.meta.filename.as_ref()) {
+                    span class="ml-2 text-gray-600" { (title) }
+                  }
+                  @if paste.meta.quarantined {
+                    span class="ml-2 text-red-600" { (TEXT[&lang]["admin-quarantined"]) }
+                  }
+                }
+                td { (paste.size) }
+                td { (paste.meta.views) }
+                td class="text-xs" {
+                  @if let Some(created) = paste.meta.created {
+                    (httpdate::fmt_http_date(std::time::UNIX_EPOCH + std::time::Duration::from_secs(created)))
+                  }
+                }
+                td class="font-mono text-xs" { (paste.meta.ip_hash.as_deref().unwrap_or("")) }
+                td class="space-x-2 whitespace-no-wrap" {
+                  @if paste.meta.quarantined {
+                    (admin_action(csrf, &format!("/admin/{}/release", paste.id), "text-green-700", TEXT[&lang]["admin-release"]))
+                  } @else {
+                    (admin_action(csrf, &format!("/admin/{}/quarantine", paste.id), "text-yellow-700", TEXT[&lang]["admin-quarantine"]))
+                  }
+                  (admin_action(csrf, &format!("/admin/{}/delete", paste.id), "text-red-600", TEXT[&lang]["delete"]))
+                  @if let Some(ip_hash) = &paste.meta.ip_hash {
+                    (admin_ban_button(csrf, BanKind::Ip, ip_hash, TEXT[&lang]["admin-ban-ip"]))
+                  }
+                }
+              }
+            }
+          }
+        }
+        div class="bg-white shadow-xl border-2 border-dashed border-gray-200 p-6" {
+          h3 class="text-lg leading-6 font-medium text-gray-900 mb-4" { (TEXT[&lang]["admin-bans"]) }
+          form action="/admin/bans" method="post" class="flex mb-4 text-sm" {
+            (csrf.field())
+            select name="kind" class="border-2 border-gray-300 px-2 py-1" {
+              option value="ip" { (TEXT[&lang]["admin-ban-ip"]) }
+              option value="key" { (TEXT[&lang]["admin-ban-key"]) }
+              option value="content" { (TEXT[&lang]["admin-ban-content"]) }
+            }
+            input name="value" required? class="flex-auto mx-2 border-2 border-gray-300 px-2 py-1 font-mono"
+                placeholder=(TEXT[&lang]["admin-ban-value"]);
+            button type="submit" class="text-red-600" { (TEXT[&lang]["admin-ban-button"]) }
+          }
+          ul class="divide-y divide-gray-200 text-sm" {
+            @for (kind, value) in bans {
+              li class="flex justify-between py-1" {
+                span { (kind.name()) " " span class="font-mono" { (value) } }
+                form action="/admin/bans/remove" method="post" {
+                  (csrf.field())
+                  input type="hidden" name="kind" value=(kind.name());
+                  input type="hidden" name="value" value=(value);
+                  button type="submit" class="text-gray-600 underline" { (TEXT[&lang]["admin-unban"]) }
+                }
+              }
+            }
+          }
+        }
+        div class="bg-white shadow-xl border-2 border-dashed border-gray-200 p-6" {
+          h3 class="text-lg leading-6 font-medium text-gray-900 mb-4" { (TEXT[&lang]["admin-audit"]) }
+          ul class="text-xs font-mono space-y-1" {
+            @for entry in audit {
+              li {
+                (httpdate::fmt_http_date(std::time::UNIX_EPOCH + std::time::Duration::from_secs(entry.time)))
+                " " (entry.admin) " " (entry.action) " " (entry.target)
+              }
+            }
+          }
+        }
+       }
+      }
+    }
+  }
+}
+
+/// A paste as an admin sees it: its metadata and the start of its text,
+/// whatever its password, quarantine or burn-after-read.
+fn admin_preview_page(
+    id: &str,
+    meta: &PasteMeta,
+    text: &str,
+    content_hash: &str,
+    csrf: &CsrfToken,
+    lang: ServerAcceptLangauge,
+) -> Markup {
+  html! {
+    (admin_head(id, &lang))
+    body {
+      div class="min-h-screen flex justify-center bg-gray-50 py-12 px-4 sm:px-6 lg:px-8" {
+       div class="max-w-3xl w-full space-y-4" {
+        a href="/admin" class="text-sm" { (TEXT[&lang]["admin-h1"]) }
+        div class="bg-white shadow-xl border-2 border-dashed border-gray-200 p-6 space-y-4 text-sm" {
+          h3 class="text-lg leading-6 font-medium text-gray-900 font-mono" {
+            (id)
+            @if meta.quarantined {
+              span class="ml-2 font-sans text-red-600" { (TEXT[&lang]["admin-quarantined"]) }
+            }
+          }
+          dl class="grid grid-cols-3 gap-1" {
+            @for (label, value) in &[
+                ("title", meta.title.clone()),
+                ("filename", meta.filename.clone()),
+                ("user", meta.user.clone()),
+                ("api_key", meta.api_key.clone()),
+                ("ip_hash", meta.ip_hash.clone()),
+                ("views", Some(meta.views.to_string())),
+                ("sha256", Some(content_hash.to_string())),
+            ] {
+              @if let Some(value) = value {
+                dt class="text-gray-600" { (label) }
+                dd class="col-span-2 font-mono break-all" { (value) }
+              }
+            }
+          }
+          @if meta.encrypted {
+            p class="text-gray-600" { (TEXT[&lang]["admin-encrypted"]) }
+          }
+          pre class="p-2 overflow-x-auto border-2 border-dashed border-gray-200 whitespace-pre-wrap break-words" { (text) }
+          div class="space-x-2" {
+            @if meta.quarantined {
+              (admin_action(csrf, &format!("/admin/{}/release", id), "text-green-700", TEXT[&lang]["admin-release"]))
+            } @else {
+              (admin_action(csrf, &format!("/admin/{}/quarantine", id), "text-yellow-700", TEXT[&lang]["admin-quarantine"]))
+            }
+            (admin_action(csrf, &format!("/admin/{}/delete", id), "text-red-600", TEXT[&lang]["delete"]))
+            (admin_ban_button(csrf, BanKind::Content, content_hash, TEXT[&lang]["admin-ban-content"]))
+            @if let Some(ip_hash) = &meta.ip_hash {
+              (admin_ban_button(csrf, BanKind::Ip, ip_hash, TEXT[&lang]["admin-ban-ip"]))
+            }
+            @if let Some(key) = &meta.api_key {
+              (admin_ban_button(csrf, BanKind::Key, key, TEXT[&lang]["admin-ban-key"]))
+            }
+          }
+        }
+       }
+      }
+    }
+  }
+}
+
 #[cfg(debug_assertions)]
-fn development_script_tag() -> Markup {
+fn development_script_tag(nonce: &Nonce) -> Markup {
     html! {
-      script src="http://127.0.0.3:35729/livereload.js" {}
+      script src="http://127.0.0.3:35729/livereload.js" nonce=(nonce.0) {}
     }
 }
 
 #[cfg(not(debug_assertions))]
-fn development_script_tag() -> Markup {
+fn development_script_tag(_nonce: &Nonce) -> Markup {
     html! { }
 }
 
 fn rocket() -> rocket::Rocket {
     rocket::ignite()
         .mount("/", routes![
-            index, favicon, 
-            robots, upload, upload_api, retrieve, retrieve_api,
-            hitcount
+            index,
+            robots, upload, upload_api, upload_put, retrieve, retrieve_api,
+            retrieve_api_named, unlock, hitcount
         ])
+        .mount("/", tus::routes())
+        .mount("/", hastebin::routes())
+        .mount("/", sprunge::routes())
+        .mount("/", live::routes())
+        .mount("/", edit::routes())
+        .mount("/", events::routes())
+        .mount("/", channel::routes())
+        .mount("/", chat::routes())
+        .mount("/", qr::routes())
+        .mount("/", pairing::routes())
+        .mount("/", ws::routes())
+        .mount("/", api_key::routes())
+        .mount("/", account::routes())
+        .mount("/", assets::routes())
+        .mount("/", moderation::routes())
+        .mount("/", report::routes())
+        .attach(tus::fairing())
+        .attach(events::fairing())
+        .attach(channel::fairing())
+        .attach(ws::fairing())
+        .attach(ws::listener())
+        .attach(termbin::listener())
+        .attach(rate_limit::fairing())
+        .attach(rate_limit::not_found_counter())
+        .attach(pow::fairing())
+        .attach(api_key::fairing())
+        .attach(api_key::check_admin_key())
+        .attach(csrf::fairing())
+        .attach(account::fairing())
+        .attach(report::fairing())
+        .attach(security_headers::fairing())
+        .attach(security_headers::headers())
+        .register(rate_limit::catchers())
         .manage(HitCount(AtomicUsize::new(0)))
+        .manage(EventHub::default())
+        .manage(Pairings::default())
+        .manage(chat::Chat::default())
+        .manage(Passwords::default())
 }
 
+const USAGE: &str = "\
+usage: pastebin [command]
+
+Without a command, runs the server. Commands:
+    mint-admin-key [NAME]   print a new API key with every scope
+    make-admin NAME         let the account NAME use the moderation panel
+";
+
 fn main() {
-    rocket().launch();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        [] => {
+            rocket().launch();
+            Ok(())
+        }
+        ["mint-admin-key"] => api_key::mint_admin("admin").map(|token| println!("{}", token)),
+        ["mint-admin-key", name] => api_key::mint_admin(name).map(|token| println!("{}", token)),
+        ["make-admin", name] => account::set_admin(name, true),
+        _ => {
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("pastebin: {}", e);
+        std::process::exit(1);
+    }
 }
 
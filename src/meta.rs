@@ -0,0 +1,291 @@
+//! Per-paste settings, stored next to the paste as `upload/<id>.meta`.
+//!
+//! The file holds one `key=value` pair per line. Pastes without a `.meta`
+//! file (e.g. created before it existed) get the defaults. The view count is
+//! kept apart in `upload/<id>.views`, so that counting a view never writes
+//! the `.meta` over a change made meanwhile, such as a quarantine.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::Outcome;
+
+use crate::paste_id::PasteID;
+
+const TOKEN_LENGTH: usize = 32;
+/// Tries at claiming a new paste ID before giving up.
+const CLAIM_ATTEMPTS: usize = 16;
+
+/// Whether a paste may be picked up by crawlers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    Unlisted,
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility::Public
+    }
+}
+
+impl Visibility {
+    pub fn parse(value: &str) -> Option<Visibility> {
+        match value {
+            "public" => Some(Visibility::Public),
+            "unlisted" => Some(Visibility::Unlisted),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+              Visibility::Public => write!(f, "public")
+            , Visibility::Unlisted => write!(f, "unlisted")
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PasteMeta {
+    /// Unix time after which the paste is deleted.
+    pub expires: Option<u64>,
+    pub burn_after_read: bool,
+    pub syntax: Option<String>,
+    pub title: Option<String>,
+    pub filename: Option<String>,
+    pub visibility: Visibility,
+    /// The paste is an `envelope.rs` envelope, decrypted in the browser.
+    pub encrypted: bool,
+    /// Argon2 hash of the password needed to read the paste.
+    pub password_hash: Option<String>,
+    /// Secret returned to the uploader, required to modify the paste.
+    pub owner_token: Option<String>,
+    /// ID of the API key the paste was uploaded with.
+    pub api_key: Option<String>,
+    /// Name of the account that uploaded the paste.
+    pub user: Option<String>,
+    /// Unix time of the upload.
+    pub created: Option<u64>,
+    /// How often the paste was read, from `upload/<id>.views`; not saved
+    /// with the rest.
+    pub views: u64,
+    /// Salted hash of the uploader's address (see `moderation::hash_ip`).
+    pub ip_hash: Option<String>,
+    /// Hidden by a moderator until released or deleted.
+    pub quarantined: bool,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn meta_path(id: &str) -> String {
+    format!("upload/{id}.meta", id = id)
+}
+
+fn views_path(id: &str) -> String {
+    format!("upload/{id}.views", id = id)
+}
+
+lazy_static! {
+    /// Serializes view counting.
+    static ref VIEWS: Mutex<()> = Mutex::new(());
+}
+
+fn views(id: &str) -> u64 {
+    fs::read_to_string(views_path(id)).ok().and_then(|text| text.trim().parse().ok()).unwrap_or(0)
+}
+
+/// Counts a view of `id`, returning the new total.
+pub fn count_view(id: &str) -> io::Result<u64> {
+    let _lock = VIEWS.lock().unwrap();
+    let views = views(id) + 1;
+    fs::write(views_path(id), views.to_string())?;
+    Ok(views)
+}
+
+impl PasteMeta {
+    /// Reads the metadata of `id`, falling back to the defaults.
+    pub fn load(id: &str) -> PasteMeta {
+        let meta = fs::read_to_string(meta_path(id))
+            .map(|text| PasteMeta::parse(&text))
+            .unwrap_or_default();
+        PasteMeta { views: views(id), ..meta }
+    }
+
+    /// Like `load`, but deletes the paste and returns `None` once it expired.
+    /// Quarantined pastes are `None` as well.
+    pub fn load_live(id: &str) -> Option<PasteMeta> {
+        let meta = PasteMeta::load(id);
+        if meta.is_expired() {
+            delete(id);
+            return None;
+        }
+        Some(meta).filter(|meta| !meta.quarantined)
+    }
+
+    fn parse(text: &str) -> PasteMeta {
+        let mut meta = PasteMeta::default();
+        for line in text.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            match key {
+                "expires" => meta.expires = value.parse().ok(),
+                "burn_after_read" => meta.burn_after_read = value == "true",
+                "syntax" => meta.syntax = Some(value.to_string()),
+                "title" => meta.title = Some(value.to_string()),
+                "filename" => meta.filename = Some(value.to_string()),
+                "visibility" => meta.visibility = Visibility::parse(value).unwrap_or_default(),
+                "encrypted" => meta.encrypted = value == "true",
+                "password_hash" => meta.password_hash = Some(value.to_string()),
+                "owner_token" => meta.owner_token = Some(value.to_string()),
+                "api_key" => meta.api_key = Some(value.to_string()),
+                "user" => meta.user = Some(value.to_string()),
+                "created" => meta.created = value.parse().ok(),
+                "ip_hash" => meta.ip_hash = Some(value.to_string()),
+                "quarantined" => meta.quarantined = value == "true",
+                _ => {}
+            }
+        }
+        meta
+    }
+
+    pub fn save(&self, id: &str) -> io::Result<()> {
+        let mut text = String::new();
+        if let Some(expires) = self.expires {
+            text.push_str(&format!("expires={}\n", expires));
+        }
+        text.push_str(&format!("burn_after_read={}\n", self.burn_after_read));
+        for (key, value) in &[("syntax", &self.syntax), ("title", &self.title), ("filename", &self.filename)] {
+            if let Some(value) = value {
+                text.push_str(&format!("{}={}\n", key, value));
+            }
+        }
+        text.push_str(&format!("visibility={}\n", self.visibility));
+        text.push_str(&format!("encrypted={}\n", self.encrypted));
+        if let Some(hash) = &self.password_hash {
+            text.push_str(&format!("password_hash={}\n", hash));
+        }
+        if let Some(token) = &self.owner_token {
+            text.push_str(&format!("owner_token={}\n", token));
+        }
+        if let Some(key) = &self.api_key {
+            text.push_str(&format!("api_key={}\n", key));
+        }
+        if let Some(user) = &self.user {
+            text.push_str(&format!("user={}\n", user));
+        }
+        if let Some(created) = self.created {
+            text.push_str(&format!("created={}\n", created));
+        }
+        if let Some(hash) = &self.ip_hash {
+            text.push_str(&format!("ip_hash={}\n", hash));
+        }
+        if self.quarantined {
+            text.push_str("quarantined=true\n");
+        }
+        fs::write(meta_path(id), text)
+    }
+
+    pub fn is_owner(&self, token: &OwnerToken) -> bool {
+        self.owner_token.as_ref().map_or(false, |expected| tokens_match(expected, &token.0))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.map_or(false, |expires| expires <= unix_now())
+    }
+}
+
+/// Compares in constant time, so a token can't be guessed byte by byte.
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub fn new_token() -> String {
+    PasteID::new(TOKEN_LENGTH).to_string()
+}
+
+/// The `X-Owner-Token` header handed out when the paste was created.
+pub struct OwnerToken(String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for OwnerToken {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Owner-Token") {
+            Some(token) => Outcome::Success(OwnerToken(token.to_string())),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// Checks the owner token of a live paste, answering with the status to
+/// return on failure.
+pub fn authorize(id: &str, token: &OwnerToken) -> Result<PasteMeta, Status> {
+    let meta = PasteMeta::load_live(id).ok_or(Status::NotFound)?;
+    match meta.is_owner(token) && Path::new(&format!("upload/{id}", id = id)).exists() {
+        true => Ok(meta),
+        false => Err(Status::Forbidden),
+    }
+}
+
+/// Claims a new paste ID of `length` characters by creating an empty
+/// `upload/<id>.partial`; IDs that are taken are skipped. The paste is
+/// written there and only moved into place by `publish`, once its meta is
+/// saved, so nobody can read it before it is checked and locked down. Fails
+/// when every attempt hit a taken ID, i.e. when short IDs are running out.
+pub fn claim_id(length: usize) -> io::Result<(PasteID<'static>, File)> {
+    for _ in 0..CLAIM_ATTEMPTS {
+        let id = PasteID::new(length);
+        let partial = partial_path(&id.to_string());
+        match OpenOptions::new().write(true).create_new(true).open(&partial) {
+            // Checked only now: an upload that published this ID moved its
+            // partial file away first.
+            Ok(..) if Path::new(&format!("upload/{id}", id = id)).exists() => {
+                let _ = fs::remove_file(&partial);
+                continue;
+            }
+            Ok(file) => return Ok((id, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::Other, "no free paste ID left"))
+}
+
+/// Where a paste claimed with `claim_id` is written before `publish`.
+pub fn partial_path(id: &str) -> String {
+    format!("upload/{id}.partial", id = id)
+}
+
+/// Makes a claimed paste readable at `upload/<id>`.
+pub fn publish(id: &str) -> io::Result<()> {
+    fs::rename(partial_path(id), format!("upload/{id}", id = id))
+}
+
+/// Whether `id` is a live paste. Unlike `open_paste`, this doesn't count as a
+/// view or burn the paste.
+pub fn exists(id: &str) -> bool {
+    PasteMeta::load_live(id).is_some() && Path::new(&format!("upload/{id}", id = id)).exists()
+}
+
+/// Removes a paste together with its metadata, view count, chat thread and
+/// reports.
+pub fn delete(id: &str) {
+    let _ = fs::remove_file(format!("upload/{id}", id = id));
+    let _ = fs::remove_file(meta_path(id));
+    let _ = fs::remove_file(views_path(id));
+    let _ = fs::remove_file(format!("upload/{id}.chat", id = id));
+    let _ = fs::remove_file(format!("upload/{id}.reports", id = id));
+}
@@ -0,0 +1,338 @@
+//! The `/admin` area, for the accounts marked as admins with
+//! `pastebin make-admin <name>` (see `account.rs`). It lists reported (see
+//! `report.rs`) and recent pastes and lets an admin preview, quarantine,
+//! release or delete them, and ban uploads by address, API key or content.
+//!
+//! Addresses are only kept as salted hashes (`hash_ip`); the salt is made on
+//! first use and kept in `upload/.salt`. Bans are `<kind> <value>` lines in
+//! `upload/.bans`, read once and then kept in memory, so change them here
+//! rather than in the file. Every action is appended to `upload/.audit.log`
+//! as `<time>\t<admin>\t<action>\t<target>`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+use maud::Markup;
+use rand::{self, Rng};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{Debug, Redirect};
+use rocket::{Outcome, Route, State};
+use sha2::{Digest, Sha256};
+
+use crate::account::{self, User};
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::events::{EventHub, PasteEvent};
+use crate::meta::{self, unix_now, PasteMeta};
+use crate::paste_id::{valid_id, PasteID};
+use crate::rate_limit;
+use crate::report;
+use crate::{admin_page, admin_preview_page, ServerAcceptLangauge};
+
+const SALT_PATH: &str = "upload/.salt";
+const BANS_PATH: &str = "upload/.bans";
+const AUDIT_PATH: &str = "upload/.audit.log";
+const RECENT_PASTES: usize = 100;
+const AUDIT_ENTRIES: usize = 50;
+/// Only this much of a paste is shown in the preview.
+const PREVIEW_LIMIT: u64 = 64 << 10;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+lazy_static! {
+    static ref SALT: String = load_salt().unwrap_or_else(|e| {
+        eprintln!("could not keep the address salt in {}: {}", SALT_PATH, e);
+        hex(&rand::thread_rng().gen::<[u8; 16]>())
+    });
+    /// The bans file, read at the first upload and kept in step by
+    /// `set_ban`.
+    static ref BANS: Mutex<Option<Vec<(BanKind, String)>>> = Mutex::new(None);
+}
+
+fn load_salt() -> io::Result<String> {
+    if let Ok(salt) = fs::read_to_string(SALT_PATH) {
+        return Ok(salt.trim().to_string());
+    }
+    let salt = hex(&rand::thread_rng().gen::<[u8; 16]>());
+    fs::write(SALT_PATH, &salt)?;
+    Ok(salt)
+}
+
+/// The salted SHA-256 of an address, shortened to 16 hex digits.
+pub fn hash_ip(ip: &IpAddr) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(SALT.as_bytes());
+    hasher.update(ip.to_string().as_bytes());
+    hex(&hasher.finalize()[..8])
+}
+
+/// Like `hash_ip`, but for the network an address is in (see
+/// `rate_limit::network`).
+pub fn hash_network(ip: &IpAddr) -> String {
+    hash_ip(&rate_limit::network(ip))
+}
+
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BanKind {
+    /// Value is an address hash.
+    Ip,
+    /// Value is an API key ID.
+    Key,
+    /// Value is the SHA-256 of a paste.
+    Content,
+}
+
+impl BanKind {
+    fn parse(value: &str) -> Option<BanKind> {
+        match value {
+            "ip" => Some(BanKind::Ip),
+            "key" => Some(BanKind::Key),
+            "content" => Some(BanKind::Content),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BanKind::Ip => "ip",
+            BanKind::Key => "key",
+            BanKind::Content => "content",
+        }
+    }
+}
+
+fn read_bans() -> Vec<(BanKind, String)> {
+    fs::read_to_string(BANS_PATH)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(kind, value)| Some((BanKind::parse(kind)?, value.to_string())))
+        .collect()
+}
+
+pub fn bans() -> Vec<(BanKind, String)> {
+    BANS.lock().unwrap().get_or_insert_with(read_bans).clone()
+}
+
+pub fn is_banned(kind: BanKind, value: &str) -> bool {
+    let mut bans = BANS.lock().unwrap();
+    bans.get_or_insert_with(read_bans).iter().any(|(banned, banned_value)| *banned == kind && banned_value == value)
+}
+
+fn set_ban(kind: BanKind, value: &str, banned: bool) -> io::Result<()> {
+    let mut cached = BANS.lock().unwrap();
+    let mut bans: Vec<(BanKind, String)> = cached.get_or_insert_with(read_bans)
+        .iter()
+        .filter(|(old, old_value)| !(*old == kind && old_value == value))
+        .cloned()
+        .collect();
+    if banned {
+        bans.push((kind, value.to_string()));
+    }
+    let text: String = bans.iter().map(|(kind, value)| format!("{} {}\n", kind.name(), value)).collect();
+    fs::write(BANS_PATH, text)?;
+    *cached = Some(bans);
+    Ok(())
+}
+
+/// Refuses a stored paste whose content is banned, deleting the file.
+pub fn check_content(path: &Path) -> io::Result<()> {
+    if !is_banned(BanKind::Content, &content_hash(path)?) {
+        return Ok(());
+    }
+    let _ = fs::remove_file(path);
+    Err(io::Error::new(io::ErrorKind::PermissionDenied, "this content is not allowed here\n"))
+}
+
+pub struct AuditEntry {
+    pub time: u64,
+    pub admin: String,
+    pub action: String,
+    pub target: String,
+}
+
+pub fn audit(admin: &str, action: &str, target: &str) -> io::Result<()> {
+    let mut log = OpenOptions::new().create(true).append(true).open(AUDIT_PATH)?;
+    writeln!(log, "{}\t{}\t{}\t{}", unix_now(), admin, action, target)
+}
+
+/// The latest entries of the audit log, newest first.
+pub fn audit_log(limit: usize) -> Vec<AuditEntry> {
+    let text = fs::read_to_string(AUDIT_PATH).unwrap_or_default();
+    text.lines().rev()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            Some(AuditEntry {
+                time: fields.next()?.parse().ok()?,
+                admin: fields.next()?.to_string(),
+                action: fields.next()?.to_string(),
+                target: fields.next()?.to_string(),
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+pub struct PasteSummary {
+    pub id: String,
+    pub size: u64,
+    pub meta: PasteMeta,
+}
+
+/// The last `limit` pastes written, quarantined ones included.
+fn recent_pastes(limit: usize) -> Vec<PasteSummary> {
+    let mut files: Vec<_> = fs::read_dir("upload")
+        .map(|entries| {
+            entries.filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let id = entry.file_name().to_str()?.to_string();
+                    let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+                    Some((id, metadata)).filter(|(id, _)| valid_id(id) && !id.is_empty())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort_by_key(|(_, metadata)| std::cmp::Reverse(metadata.modified().ok()));
+    files.into_iter()
+        .take(limit)
+        .map(|(id, metadata)| PasteSummary { meta: PasteMeta::load(&id), size: metadata.len(), id })
+        .collect()
+}
+
+/// A logged-in admin account. Forwards when nobody is logged in, so that
+/// `/admin` can send them to the login form.
+pub struct Moderator {
+    pub name: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Moderator {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let user = match request.guard::<User>() {
+            Outcome::Success(user) => user,
+            _ => return Outcome::Forward(()),
+        };
+        match account::is_admin(&user.name) {
+            true => Outcome::Success(Moderator { name: user.name }),
+            false => Outcome::Failure((Status::Forbidden, ())),
+        }
+    }
+}
+
+#[get("/admin")]
+fn admin(moderator: Moderator, csrf: CsrfToken, lang: ServerAcceptLangauge) -> Markup {
+    let pastes = recent_pastes(RECENT_PASTES);
+    admin_page(&moderator.name, &report::queue(), &pastes, &bans(), &audit_log(AUDIT_ENTRIES), &csrf, lang)
+}
+
+#[get("/admin", rank = 2)]
+fn admin_login() -> Redirect {
+    Redirect::to("/login")
+}
+
+#[get("/admin/<id>")]
+fn preview(_moderator: Moderator, id: PasteID<'_>, csrf: CsrfToken, lang: ServerAcceptLangauge) -> Option<Markup> {
+    let id = id.to_string();
+    let path = format!("upload/{id}", id = id);
+    let mut text = Vec::new();
+    File::open(&path).ok()?.take(PREVIEW_LIMIT).read_to_end(&mut text).ok()?;
+    let hash = content_hash(Path::new(&path)).ok()?;
+    let meta = PasteMeta::load(&id);
+    Some(admin_preview_page(&id, &meta, &String::from_utf8_lossy(&text), &hash, &csrf, lang))
+}
+
+fn back() -> Redirect {
+    Redirect::to("/admin")
+}
+
+#[post("/admin/<id>/delete", data = "<_csrf>")]
+fn delete(moderator: Moderator, id: PasteID<'_>, hub: State<EventHub>, _csrf: CsrfForm) -> Result<Redirect, Debug<io::Error>> {
+    let id = id.to_string();
+    meta::delete(&id);
+    hub.publish(&id, PasteEvent::Deleted);
+    audit(&moderator.name, "delete", &id)?;
+    Ok(back())
+}
+
+fn set_quarantined(moderator: &Moderator, id: &str, quarantined: bool) -> io::Result<Option<Redirect>> {
+    if !Path::new(&format!("upload/{id}", id = id)).exists() {
+        return Ok(None);
+    }
+    let meta = PasteMeta { quarantined, ..PasteMeta::load(id) };
+    meta.save(id)?;
+    // Released pastes were looked at; their reports are done with.
+    if !quarantined {
+        report::dismiss(id);
+    }
+    audit(&moderator.name, if quarantined { "quarantine" } else { "release" }, id)?;
+    Ok(Some(back()))
+}
+
+/// Hides the paste, keeping it for review.
+#[post("/admin/<id>/quarantine", data = "<_csrf>")]
+fn quarantine(moderator: Moderator, id: PasteID<'_>, hub: State<EventHub>, _csrf: CsrfForm) -> Result<Option<Redirect>, Debug<io::Error>> {
+    let id = id.to_string();
+    hub.publish(&id, PasteEvent::Deleted);
+    Ok(set_quarantined(&moderator, &id, true)?)
+}
+
+#[post("/admin/<id>/release", data = "<_csrf>")]
+fn release(moderator: Moderator, id: PasteID<'_>, _csrf: CsrfForm) -> Result<Option<Redirect>, Debug<io::Error>> {
+    Ok(set_quarantined(&moderator, &id.to_string(), false)?)
+}
+
+#[derive(FromForm)]
+struct BanForm {
+    kind: String,
+    value: String,
+}
+
+impl BanForm {
+    /// The ban to record; a plain address is hashed first.
+    fn parse(&self) -> Option<(BanKind, String)> {
+        let kind = BanKind::parse(&self.kind)?;
+        let value = self.value.trim();
+        let value = match (kind, value.parse::<IpAddr>()) {
+            (BanKind::Ip, Ok(ip)) => hash_ip(&ip),
+            _ => value.to_string(),
+        };
+        Some((kind, value)).filter(|(_, value)| !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric()))
+    }
+}
+
+#[post("/admin/bans", data = "<form>")]
+fn ban(moderator: Moderator, form: CsrfForm<BanForm>) -> Result<Option<Redirect>, Debug<io::Error>> {
+    let (kind, value) = match form.parse() {
+        Some(ban) => ban,
+        None => return Ok(None),
+    };
+    set_ban(kind, &value, true)?;
+    audit(&moderator.name, &format!("ban {}", kind.name()), &value)?;
+    Ok(Some(back()))
+}
+
+#[post("/admin/bans/remove", data = "<form>")]
+fn unban(moderator: Moderator, form: CsrfForm<BanForm>) -> Result<Option<Redirect>, Debug<io::Error>> {
+    let (kind, value) = match form.parse() {
+        Some(ban) => ban,
+        None => return Ok(None),
+    };
+    set_ban(kind, &value, false)?;
+    audit(&moderator.name, &format!("unban {}", kind.name()), &value)?;
+    Ok(Some(back()))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![admin, admin_login, preview, delete, quarantine, release, ban, unban]
+}
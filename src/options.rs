@@ -0,0 +1,151 @@
+//! Options for `POST /api/paste`, given either as query parameters
+//! (`?expiry=1h&burn`) or as `X-Paste-*` headers (`X-Paste-Expiry: 1h`).
+//! Headers are applied first, so the query string wins on conflicts. The
+//! password is only accepted as a header, since URLs end up in logs.
+
+use rocket::http::Status;
+use rocket::request::{self, FormItems, FromRequest, Request};
+use rocket::Outcome;
+
+use crate::meta::{unix_now, PasteMeta, Visibility};
+use crate::password;
+use crate::ID_LENGTH;
+
+const HEADER_PREFIX: &str = "x-paste-";
+const MAX_ID_LENGTH: usize = 32;
+
+/// Every accepted option: query name, header name and a short description
+/// for the API docs.
+pub const UPLOAD_OPTIONS: &[(&str, &str, &str)] = &[
+    ("expiry", "X-Paste-Expiry", "delete after e.g. `600`, `10m`, `2h`, `7d` or `never`"),
+    ("burn", "X-Paste-Burn", "delete after the first read (`true`/`false`)"),
+    ("syntax", "X-Paste-Syntax", "language for highlighting, e.g. `rust`"),
+    ("title", "X-Paste-Title", "title shown with the paste"),
+    ("filename", "X-Paste-Filename", "file name used when downloading"),
+    ("visibility", "X-Paste-Visibility", "`public` or `unlisted` (hidden from crawlers)"),
+    ("id_length", "X-Paste-Id-Length", "length of the generated id, 3 to 32"),
+    ("encrypted", "X-Paste-Encrypted", "the body is an encrypted envelope (`true`/`false`); needs an API key or `pow`, see below"),
+    ("password", "X-Paste-Password", "password needed to read the paste (header only)"),
+];
+
+#[derive(Debug)]
+pub struct UploadOptions {
+    pub id_length: usize,
+    pub meta: PasteMeta,
+}
+
+impl Default for UploadOptions {
+    fn default() -> UploadOptions {
+        UploadOptions { id_length: ID_LENGTH, meta: PasteMeta::default() }
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "" | "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses `never`, plain seconds or a number with an `s`/`m`/`h`/`d`/`w` suffix.
+fn parse_expiry(value: &str) -> Option<Option<u64>> {
+    if value == "never" {
+        return Some(None);
+    }
+    let (number, unit) = match value.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&value[..i], c),
+        _ => (value, 's'),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let seconds = number.parse::<u64>().ok()?.checked_mul(scale)?;
+    Some(Some(unix_now().checked_add(seconds)?))
+}
+
+/// Titles and file names end up in headers and in the `.meta` file, so
+/// control characters (newlines in particular) are not allowed.
+fn parse_text(value: &str, max_len: usize) -> Option<String> {
+    match value.is_empty() || value.len() > max_len || value.chars().any(char::is_control) {
+        true => None,
+        false => Some(value.to_string()),
+    }
+}
+
+impl UploadOptions {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid value `{}` for option `{}`\n", value, name);
+        match name {
+            "expiry" => self.meta.expires = parse_expiry(value).ok_or_else(invalid)?,
+            "burn" => self.meta.burn_after_read = parse_bool(value).ok_or_else(invalid)?,
+            "syntax" => {
+                let valid = !value.is_empty() && value.len() <= 32
+                    && value.chars().all(|c| c.is_ascii_alphanumeric() || "+#-_".contains(c));
+                match valid {
+                    true => self.meta.syntax = Some(value.to_ascii_lowercase()),
+                    false => return Err(invalid()),
+                }
+            }
+            "title" => self.meta.title = Some(parse_text(value, 200).ok_or_else(invalid)?),
+            "filename" => {
+                let filename = parse_text(value, 255).ok_or_else(invalid)?;
+                if filename.contains(|c| c == '/' || c == '\\' || c == '"') {
+                    return Err(invalid());
+                }
+                self.meta.filename = Some(filename);
+            }
+            "visibility" => self.meta.visibility = Visibility::parse(value).ok_or_else(invalid)?,
+            "encrypted" => self.meta.encrypted = parse_bool(value).ok_or_else(invalid)?,
+            "password" => {
+                if value.is_empty() || value.len() > 1024 {
+                    return Err("the password must have 1 to 1024 bytes\n".into());
+                }
+                self.meta.password_hash = Some(password::hash(value)?);
+            }
+            "id_length" => {
+                self.id_length = value.parse().ok()
+                    .filter(|len| (ID_LENGTH..=MAX_ID_LENGTH).contains(len))
+                    .ok_or_else(invalid)?;
+            }
+            _ => {
+                let known: Vec<&str> = UPLOAD_OPTIONS.iter().map(|(name, _, _)| *name).collect();
+                return Err(format!("unknown option `{}`, expected one of: {}\n", name, known.join(", ")));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for UploadOptions {
+    type Error = String;
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let mut options = UploadOptions::default();
+        let mut result = Ok(());
+        for header in request.headers().iter() {
+            let name = header.name().as_str().to_ascii_lowercase();
+            if name.starts_with(HEADER_PREFIX) {
+                let option = name[HEADER_PREFIX.len()..].replace('-', "_");
+                result = result.and_then(|_| options.set(&option, header.value()));
+            }
+        }
+        for item in FormItems::from(request.uri().query().unwrap_or("")) {
+            let (key, value) = item.key_value_decoded();
+            result = result.and_then(|_| match key.as_str() {
+                "password" => Err("send the password in the X-Paste-Password header\n".to_string()),
+                // Read by the `ProofOfWork` guard.
+                "pow" => Ok(()),
+                _ => options.set(&key, &value),
+            });
+        }
+        match result {
+            Ok(()) => Outcome::Success(options),
+            Err(message) => Outcome::Failure((Status::BadRequest, message)),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Pairing two devices with a short-lived 6-digit code. The first device asks
+//! for a code, the second enters it, and both end up with the name and token
+//! of the same clipboard channel (see `channel.rs`). The channel of a code
+//! nobody entered is deleted when the code expires.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::{self, Rng};
+use rocket::http::{RawStr, Status};
+use rocket::request::Request;
+use rocket::response::{self, Debug, Responder, Response};
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use crate::channel::Channel;
+use crate::meta::new_token;
+use crate::moderation::hash_network;
+use crate::paste_id::PasteID;
+use crate::rate_limit::{ClientIp, RateLimiter, UploadLimit};
+
+const CODE_LIFETIME: Duration = Duration::from_secs(60);
+const CHANNEL_NAME_LENGTH: usize = 12;
+/// Codes handed out at once; far fewer than the million possible ones, so a
+/// free code turns up within `CODE_ATTEMPTS` draws.
+const MAX_PENDING: usize = 10_000;
+const CODE_ATTEMPTS: usize = 32;
+/// Guesses from everyone together per `CODE_LIFETIME`, so that many
+/// addresses can't share the work: even with `MAX_PENDING` codes out, about
+/// one guess in this many finds one.
+const MAX_GUESSES: u32 = 100;
+
+struct Pending {
+    channel: String,
+    token: String,
+    expires: Instant,
+}
+
+/// Codes waiting to be entered on the second device, plus the limits on
+/// guessing them: a few attempts per network and `MAX_GUESSES` overall.
+pub struct Pairings {
+    pending: Mutex<HashMap<String, Pending>>,
+    per_client: RateLimiter,
+    guesses: RateLimiter,
+}
+
+impl Default for Pairings {
+    fn default() -> Pairings {
+        Pairings {
+            pending: Mutex::new(HashMap::new()),
+            per_client: RateLimiter::new(5, Duration::from_secs(60)),
+            guesses: RateLimiter::new(MAX_GUESSES, CODE_LIFETIME),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PairingCode {
+    code: String,
+    expires_in: u64,
+    channel: String,
+    token: String,
+}
+
+#[derive(Serialize)]
+pub struct Paired {
+    channel: String,
+    token: String,
+}
+
+/// Outcome of entering a code.
+pub enum PairResponse {
+    Paired(Json<Paired>),
+    Unknown,
+    TooManyAttempts(Duration),
+}
+
+impl<'r> Responder<'r> for PairResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            PairResponse::Paired(json) => json.respond_to(request),
+            PairResponse::Unknown => Err(Status::NotFound),
+            PairResponse::TooManyAttempts(wait) => Response::build()
+                .status(Status::TooManyRequests)
+                .raw_header("Retry-After", (wait.as_secs() + 1).to_string())
+                .ok(),
+        }
+    }
+}
+
+fn new_code() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0, 1_000_000))
+}
+
+/// Drops expired codes together with their channels, which nobody paired
+/// with.
+fn expire(pending: &mut HashMap<String, Pending>, now: Instant) {
+    pending.retain(|_, pairing| {
+        if pairing.expires > now {
+            return true;
+        }
+        let _ = Channel::open(&pairing.channel).remove();
+        false
+    });
+}
+
+#[derive(Responder)]
+pub enum CodeError {
+    /// 503 when no free code is left.
+    Busy(Status),
+    Io(Debug<io::Error>),
+}
+
+impl From<io::Error> for CodeError {
+    fn from(error: io::Error) -> Self {
+        CodeError::Io(Debug(error))
+    }
+}
+
+#[post("/api/pair")]
+fn request_code(_limit: UploadLimit, pairings: State<Pairings>) -> Result<Json<PairingCode>, CodeError> {
+    let now = Instant::now();
+    let mut pending = pairings.pending.lock().unwrap();
+    expire(&mut pending, now);
+    if pending.len() >= MAX_PENDING {
+        return Err(CodeError::Busy(Status::ServiceUnavailable));
+    }
+    let code = (0..CODE_ATTEMPTS)
+        .map(|_| new_code())
+        .find(|code| !pending.contains_key(code))
+        .ok_or(CodeError::Busy(Status::ServiceUnavailable))?;
+
+    let channel = format!("pair-{}", PasteID::new(CHANNEL_NAME_LENGTH));
+    let token = new_token();
+    Channel::open(&channel).claim(&token)?;
+    pending.insert(code.clone(), Pending {
+        channel: channel.clone(),
+        token: token.clone(),
+        expires: now + CODE_LIFETIME,
+    });
+    Ok(Json(PairingCode { code, expires_in: CODE_LIFETIME.as_secs(), channel, token }))
+}
+
+#[post("/api/pair/<code>")]
+fn enter_code(code: &RawStr, client: ClientIp, pairings: State<Pairings>) -> PairResponse {
+    let network = client.0.as_ref().map(hash_network).unwrap_or_default();
+    if let Err(wait) = pairings.per_client.check(&network).and_then(|_| pairings.guesses.check("")) {
+        return PairResponse::TooManyAttempts(wait);
+    }
+    // Codes are single use.
+    let pairing = pairings.pending.lock().unwrap().remove(code.as_str());
+    match pairing {
+        Some(pairing) if pairing.expires > Instant::now() => {
+            PairResponse::Paired(Json(Paired { channel: pairing.channel, token: pairing.token }))
+        }
+        Some(pairing) => {
+            let _ = Channel::open(&pairing.channel).remove();
+            PairResponse::Unknown
+        }
+        None => PairResponse::Unknown,
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![request_code, enter_code]
+}
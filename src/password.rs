@@ -0,0 +1,108 @@
+//! Password-protected pastes. The password is kept as an Argon2 hash in the
+//! paste's metadata. Readers enter it on the paste page, or send it with
+//! HTTP Basic auth (the user name is ignored) or an `X-Paste-Password`
+//! header:
+//!
+//!     curl -u :hunter2 https://copy.red/api/<id>
+
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::Outcome;
+
+use crate::meta::PasteMeta;
+use crate::rate_limit::{ClientIp, RateLimiter};
+
+const PASSWORD_HEADER: &str = "X-Paste-Password";
+
+pub fn hash(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("could not hash the password: {}\n", e))
+}
+
+pub fn verify(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+/// The password sent with a request, if any.
+pub struct Password(pub Option<String>);
+
+fn basic_auth_password(header: &str) -> Option<String> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    decoded.split_once(':').map(|(_, password)| password.to_string())
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Password {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let password = headers.get_one(PASSWORD_HEADER).map(String::from)
+            .or_else(|| headers.get_one("Authorization").and_then(basic_auth_password));
+        Outcome::Success(Password(password))
+    }
+}
+
+/// Why a protected paste stays locked.
+#[derive(Debug)]
+pub enum Locked {
+    Required,
+    Wrong,
+    TooManyAttempts(Duration),
+}
+
+impl<'r> Responder<'r> for Locked {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        match self {
+            Locked::Required | Locked::Wrong => Response::build()
+                .status(Status::Unauthorized)
+                .raw_header("WWW-Authenticate", r#"Basic realm="password-protected paste", charset="UTF-8""#)
+                .ok(),
+            Locked::TooManyAttempts(wait) => Response::build()
+                .status(Status::TooManyRequests)
+                .raw_header("Retry-After", (wait.as_secs() + 1).to_string())
+                .ok(),
+        }
+    }
+}
+
+/// Limits wrong guesses per paste and address, so that someone guessing
+/// can't lock everyone else out.
+pub struct Passwords(RateLimiter);
+
+impl Default for Passwords {
+    fn default() -> Passwords {
+        Passwords(RateLimiter::new(5, Duration::from_secs(60)))
+    }
+}
+
+impl Passwords {
+    /// Checks `password` if the paste `id`, with `meta`, has one. Call it
+    /// before `open_paste`, which counts the view and burns the paste.
+    pub fn unlock(&self, id: &str, meta: &PasteMeta, client: &ClientIp, password: Option<&str>) -> Result<(), Locked> {
+        let hash = match &meta.password_hash {
+            Some(hash) => hash,
+            None => return Ok(()),
+        };
+        let password = password.ok_or(Locked::Required)?;
+        let key = format!("{} {}", id, client.key());
+        self.0.peek(&key).map_err(Locked::TooManyAttempts)?;
+        match verify(hash, password) {
+            true => Ok(()),
+            false => {
+                let _ = self.0.check(&key);
+                Err(Locked::Wrong)
+            }
+        }
+    }
+}
@@ -33,8 +33,9 @@ impl<'a> fmt::Display for PasteID<'a> {
     }
 }
 
-/// Returns `true` if `id` is a valid paste ID and `false` otherwise.
-fn valid_id(id: &str) -> bool {
+/// Returns `true` if `id` is a valid paste ID and `false` otherwise. Also
+/// used by `pastebin-cli`, which includes this file.
+pub fn valid_id(id: &str) -> bool {
     id.chars().all(|c| c.is_ascii_alphanumeric())
 }
 
@@ -0,0 +1,170 @@
+//! Proof-of-work for the paste and registration forms, in place of a
+//! CAPTCHA. The page embeds a challenge signed with a per-process key:
+//!
+//!     <difficulty>.<expires>.<nonce>.<signature>
+//!
+//! and the browser looks for a counter such that the SHA-256 of
+//! `<challenge>.<counter>` starts with `difficulty` zero bits. The solution
+//! comes back as `?pow=<challenge>.<counter>` on the form's URL, so it is
+//! checked before the form is read. Every challenge is good for one
+//! submission.
+//!
+//! The difficulty is `pow_difficulty` from `Rocket.toml` (0 disables it),
+//! plus a bit for every doubling of the recent submissions past
+//! `BUSY_THRESHOLD` a minute. Uploads through `/api` don't need it, and
+//! neither do requests with an API key.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use rand::{self, Rng};
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use sha2::{Digest, Sha256};
+
+use crate::api_key::ApiKey;
+use crate::meta::unix_now;
+
+/// How long a page may sit open before its challenge runs out.
+const LIFETIME: Duration = Duration::from_secs(600);
+const BUSY_THRESHOLD: usize = 10;
+const MAX_EXTRA_BITS: u32 = 8;
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte != 0 {
+            return bits + byte.leading_zeros();
+        }
+        bits += 8;
+    }
+    bits
+}
+
+pub struct Challenges {
+    key: [u8; 32],
+    difficulty: u32,
+    /// Nonces of solved challenges, until they expire.
+    used: Mutex<HashMap<String, u64>>,
+    /// Accepted submissions in the last minute.
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+impl Challenges {
+    pub fn new(difficulty: u32) -> Challenges {
+        Challenges {
+            key: rand::thread_rng().gen(),
+            difficulty,
+            used: Mutex::new(HashMap::new()),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn recent_submissions(&self) -> usize {
+        let mut recent = self.recent.lock().unwrap();
+        while recent.front().map_or(false, |time| time.elapsed() > Duration::from_secs(60)) {
+            recent.pop_front();
+        }
+        recent.len()
+    }
+
+    /// A fresh challenge for a page, or an empty string when disabled.
+    pub fn issue(&self) -> String {
+        if self.difficulty == 0 {
+            return String::new();
+        }
+        let busy = self.recent_submissions() / BUSY_THRESHOLD;
+        let extra = (usize::BITS - busy.leading_zeros()).min(MAX_EXTRA_BITS);
+        let nonce: [u8; 12] = rand::thread_rng().gen();
+        let payload = format!(
+            "{difficulty}.{expires}.{nonce}",
+            difficulty = self.difficulty + extra,
+            expires = unix_now() + LIFETIME.as_secs(),
+            nonce = encode(&nonce),
+        );
+        let signature = encode(&self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Checks a `<challenge>.<counter>` solution and uses up its challenge.
+    pub fn verify(&self, solution: &str) -> Result<(), &'static str> {
+        if self.difficulty == 0 {
+            return Ok(());
+        }
+        let parts: Vec<&str> = solution.split('.').collect();
+        let (difficulty, expires, nonce, signature) = match parts[..] {
+            [difficulty, expires, nonce, signature, _counter] => (difficulty, expires, nonce, signature),
+            _ => return Err("malformed proof of work\n"),
+        };
+        let payload = format!("{}.{}.{}", difficulty, expires, nonce);
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).unwrap_or_default();
+        if self.mac(&payload).verify_slice(&signature).is_err() {
+            return Err("proof of work has a bad signature\n");
+        }
+        let (difficulty, expires) = match (difficulty.parse::<u32>(), expires.parse::<u64>()) {
+            (Ok(difficulty), Ok(expires)) => (difficulty, expires),
+            _ => return Err("malformed proof of work\n"),
+        };
+        let now = unix_now();
+        if expires < now {
+            return Err("proof of work expired, reload the page\n");
+        }
+        if leading_zero_bits(&Sha256::digest(solution.as_bytes())) < difficulty {
+            return Err("proof of work is not solved\n");
+        }
+        let mut used = self.used.lock().unwrap();
+        used.retain(|_, expires| *expires >= now);
+        if used.insert(nonce.to_string(), expires).is_some() {
+            return Err("proof of work was already used\n");
+        }
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_back(Instant::now());
+        if recent.len() > BUSY_THRESHOLD << MAX_EXTRA_BITS {
+            recent.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Guard for the paste and registration forms. Fails with 403 unless the request carries a
+/// solved challenge or an API key.
+pub struct ProofOfWork;
+
+impl<'a, 'r> FromRequest<'a, 'r> for ProofOfWork {
+    type Error = &'static str;
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let challenges = match request.guard::<State<Challenges>>() {
+            Outcome::Success(challenges) => challenges,
+            _ => return Outcome::Success(ProofOfWork),
+        };
+        if request.guard::<ApiKey>().is_success() {
+            return Outcome::Success(ProofOfWork);
+        }
+        let solution = request.get_query_value::<String>("pow").and_then(Result::ok).unwrap_or_default();
+        match challenges.verify(&solution) {
+            Ok(()) => Outcome::Success(ProofOfWork),
+            Err(reason) => Outcome::Failure((Status::Forbidden, reason)),
+        }
+    }
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("proof of work", |rocket| {
+        let difficulty = rocket.config().get_int("pow_difficulty").unwrap_or(16).max(0).min(32) as u32;
+        Ok(rocket.manage(Challenges::new(difficulty)))
+    })
+}
@@ -0,0 +1,74 @@
+//! QR codes of paste URLs, rendered on the server.
+//!
+//!     curl https://copy.red/api/<id>/qr.png > qr.png
+
+use std::io;
+
+use image::codecs::png::PngEncoder;
+use image::{ColorType, Luma};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rocket::http::ContentType;
+use rocket::response::{content::Content, Debug};
+use rocket::Route;
+
+use crate::meta;
+use crate::paste_id::PasteID;
+use crate::rate_limit::ReadLimit;
+use crate::HOST;
+
+const SVG_SIZE: u32 = 128;
+const PNG_SIZE: u32 = 256;
+
+fn encode(data: &str) -> io::Result<QrCode> {
+    QrCode::new(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// An SVG document of the QR code for `data`.
+pub fn svg(data: &str) -> io::Result<String> {
+    Ok(encode(data)?.render::<svg::Color<'_>>().min_dimensions(SVG_SIZE, SVG_SIZE).build())
+}
+
+/// Like `svg`, without the XML declaration, for embedding in a page.
+pub fn inline_svg(data: &str) -> io::Result<String> {
+    let document = svg(data)?;
+    let start = document.find("<svg").unwrap_or(0);
+    Ok(document[start..].to_string())
+}
+
+pub fn png(data: &str) -> io::Result<Vec<u8>> {
+    let image = encode(data)?.render::<Luma<u8>>().min_dimensions(PNG_SIZE, PNG_SIZE).build();
+    let mut bytes = Vec::new();
+    PngEncoder::new(&mut bytes)
+        .encode(image.as_raw(), image.width(), image.height(), ColorType::L8)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    Ok(bytes)
+}
+
+/// The page URL of a paste, which is what the codes point to.
+fn paste_url(id: &PasteID<'_>) -> Option<String> {
+    match meta::exists(&id.to_string()) {
+        true => Some(format!("{host}/{id}", host = HOST, id = id)),
+        false => None,
+    }
+}
+
+#[get("/api/<id>/qr.svg")]
+fn qr_svg(_limit: ReadLimit, id: PasteID<'_>) -> Result<Option<Content<String>>, Debug<io::Error>> {
+    match paste_url(&id) {
+        Some(url) => Ok(Some(Content(ContentType::SVG, svg(&url)?))),
+        None => Ok(None),
+    }
+}
+
+#[get("/api/<id>/qr.png")]
+fn qr_png(_limit: ReadLimit, id: PasteID<'_>) -> Result<Option<Content<Vec<u8>>>, Debug<io::Error>> {
+    match paste_url(&id) {
+        Some(url) => Ok(Some(Content(ContentType::PNG, png(&url)?))),
+        None => Ok(None),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![qr_svg, qr_png]
+}
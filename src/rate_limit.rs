@@ -0,0 +1,260 @@
+//! Token-bucket rate limiting keyed by an arbitrary string, usually the
+//! client address.
+//!
+//! Per-address limits on uploads, reads and 404s are applied with the
+//! `UploadLimit` and `ReadLimit` guards; requests over the limit get a 429
+//! with `Retry-After`. Each limit is per minute in `Rocket.toml` (0 turns it
+//! off), next to the proxies whose `X-Forwarded-For` is trusted.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::{Catcher, Config, Outcome, State};
+
+const PERIOD: Duration = Duration::from_secs(60);
+
+/// Buckets are pruned once there are this many; full ones are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Allows `capacity` requests in a burst, refilling at `capacity` per `period`.
+pub struct RateLimiter {
+    capacity: f64,
+    per_second: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, period: Duration) -> RateLimiter {
+        RateLimiter {
+            capacity: f64::from(capacity.max(1)),
+            per_second: f64::from(capacity.max(1)) / period.as_secs_f64().max(0.001),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `key`. On `Err` nothing is taken and the duration
+    /// says how long until the next token is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            let (capacity, per_second) = (self.capacity, self.per_second);
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < capacity
+            });
+        }
+        let bucket = buckets.entry(key.to_string())
+            .or_insert(Bucket { tokens: self.capacity, updated: now });
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+        }
+    }
+
+    /// Like `check`, but never takes a token; for limits that only count
+    /// failures, which are then recorded with `check`.
+    pub fn peek(&self, key: &str) -> Result<(), Duration> {
+        let buckets = self.buckets.lock().unwrap();
+        let bucket = match buckets.get(key) {
+            Some(bucket) => bucket,
+            None => return Ok(()),
+        };
+        let refill = Instant::now().duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        let tokens = (bucket.tokens + refill).min(self.capacity);
+        match tokens >= 1.0 {
+            true => Ok(()),
+            false => Err(Duration::from_secs_f64((1.0 - tokens) / self.per_second)),
+        }
+    }
+}
+
+/// The address requests are rate limited by: the peer, or the client it
+/// forwarded for if it is a trusted proxy. Never fails; requests with no
+/// known address share one bucket.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    /// The bucket key, which is the client's `network`: a single IPv6
+    /// connection usually gets a whole /64 to pick addresses from.
+    pub fn key(&self) -> String {
+        self.0.as_ref().map(|ip| network(ip).to_string()).unwrap_or_default()
+    }
+}
+
+/// The network an address is counted as: IPv4 addresses stay as they are,
+/// IPv6 ones are cut to their /64.
+pub fn network(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => {
+                let segments = v6.segments();
+                IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3], 0, 0, 0, 0))
+            }
+        },
+        IpAddr::V4(_) => *ip,
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let peer = request.remote().map(|address| address.ip());
+        let limits = request.guard::<State<RateLimits>>().succeeded();
+        let ip = match (peer, limits) {
+            (Some(peer), Some(limits)) if limits.trusted_proxies.contains(&peer) => {
+                forwarded_for(request.headers().get("X-Forwarded-For"), &limits.trusted_proxies).or(Some(peer))
+            }
+            _ => peer,
+        };
+        Outcome::Success(ClientIp(ip))
+    }
+}
+
+/// The last address in the `X-Forwarded-For` headers that isn't a trusted
+/// proxy; anything before it could have been made up by the client. Also
+/// used by the WebSocket listener, which has no rocket `Request`.
+pub fn forwarded_for<'h, I>(headers: I, trusted: &[IpAddr]) -> Option<IpAddr>
+where
+    I: IntoIterator<Item = &'h str>,
+{
+    let addresses: Vec<&str> = headers.into_iter().flat_map(|value| value.split(',')).collect();
+    addresses.iter().rev()
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .find(|ip| !trusted.contains(ip))
+}
+
+/// Limits from `rate_limit_uploads`, `rate_limit_reads` and
+/// `rate_limit_not_found`, and the `trusted_proxies`.
+pub struct RateLimits {
+    uploads: Option<RateLimiter>,
+    reads: Option<RateLimiter>,
+    not_found: Option<RateLimiter>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+/// Seconds to put in `Retry-After`, handed from a failing guard to the catcher.
+struct RetryAfter(AtomicU64);
+
+fn limit<F>(request: &Request<'_>, check: F) -> request::Outcome<(), ()>
+where
+    F: FnOnce(&RateLimits, &str) -> Result<(), Duration>,
+{
+    let limits = match request.guard::<State<RateLimits>>() {
+        Outcome::Success(limits) => limits,
+        _ => return Outcome::Success(()),
+    };
+    let client = ClientIp::from_request(request).map(|client| client.key()).succeeded().unwrap_or_default();
+    match check(&limits, &client) {
+        Ok(()) => Outcome::Success(()),
+        Err(wait) => {
+            let retry_after = request.local_cache(|| RetryAfter(AtomicU64::new(0)));
+            retry_after.0.store(wait.as_secs() + 1, Ordering::Relaxed);
+            Outcome::Failure((Status::TooManyRequests, ()))
+        }
+    }
+}
+
+fn check(limiter: &Option<RateLimiter>, key: &str) -> Result<(), Duration> {
+    limiter.as_ref().map_or(Ok(()), |limiter| limiter.check(key))
+}
+
+/// Guard for routes that store something.
+pub struct UploadLimit;
+
+impl<'a, 'r> FromRequest<'a, 'r> for UploadLimit {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        limit(request, |limits, client| check(&limits.uploads, client)).map(|_| UploadLimit)
+    }
+}
+
+/// Guard for routes that read a paste. Clients that ran out of 404s, e.g. by
+/// trying IDs in turn, are refused too.
+pub struct ReadLimit;
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReadLimit {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        limit(request, |limits, client| {
+            let not_found = limits.not_found.as_ref().map_or(Ok(()), |limiter| limiter.peek(client));
+            not_found.and_then(|_| check(&limits.reads, client))
+        })
+        .map(|_| ReadLimit)
+    }
+}
+
+pub struct TooManyRequests(u64);
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from("too many requests, slow down\n".respond_to(request)?)
+            .status(Status::TooManyRequests)
+            .raw_header("Retry-After", self.0.to_string())
+            .ok()
+    }
+}
+
+#[catch(429)]
+fn too_many_requests(request: &Request) -> TooManyRequests {
+    let retry_after = request.local_cache(|| RetryAfter(AtomicU64::new(PERIOD.as_secs())));
+    TooManyRequests(retry_after.0.load(Ordering::Relaxed))
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![too_many_requests]
+}
+
+/// `trusted_proxies` from `Rocket.toml`.
+pub fn trusted_proxies(config: &Config) -> Vec<IpAddr> {
+    config.get_slice("trusted_proxies")
+        .map(|proxies| proxies.iter().filter_map(|proxy| proxy.as_str()?.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("rate limits", |rocket| {
+        let config = rocket.config();
+        let limiter = |name: &str, default: i64| match config.get_int(name).unwrap_or(default) {
+            0 => None,
+            capacity => Some(RateLimiter::new(capacity.max(1) as u32, PERIOD)),
+        };
+        let limits = RateLimits {
+            uploads: limiter("rate_limit_uploads", 30),
+            reads: limiter("rate_limit_reads", 600),
+            not_found: limiter("rate_limit_not_found", 60),
+            trusted_proxies: trusted_proxies(config),
+        };
+        Ok(rocket.manage(limits))
+    })
+}
+
+/// Charges every 404 to the client's `rate_limit_not_found` budget.
+pub fn not_found_counter() -> impl Fairing {
+    AdHoc::on_response("404 rate limit", |request, response| {
+        if response.status() != Status::NotFound {
+            return;
+        }
+        if let Outcome::Success(limits) = request.guard::<State<RateLimits>>() {
+            let client = ClientIp::from_request(request).map(|client| client.key()).succeeded().unwrap_or_default();
+            let _ = check(&limits.not_found, &client);
+        }
+    })
+}
@@ -0,0 +1,258 @@
+//! Abuse reports. Anyone can report a paste with a reason, from the form on
+//! its page or as JSON:
+//!
+//!     curl -H 'Content-Type: application/json' \
+//!          -d '{"reason": "phishing", "details": "fake bank login"}' \
+//!          https://copy.red/api/<id>/report
+//!
+//! Reports are stored as JSON lines in `upload/<id>.reports`, with the
+//! reporter's address only as a salted hash (`moderation::hash_network`);
+//! one report counts per IPv4 address or IPv6 /64. Reported pastes are
+//! queued on `/admin` until an admin deletes or releases them, and once
+//! `report_threshold` addresses reported one (0 turns this off) it is
+//! quarantined in the meantime.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Debug, Redirect, Responder, Response};
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::csrf::CsrfForm;
+use crate::events::{EventHub, PasteEvent};
+use crate::meta::{self, unix_now, PasteMeta};
+use crate::moderation::{self, hash_network};
+use crate::paste_id::{valid_id, PasteID};
+use crate::rate_limit::{ClientIp, RateLimiter};
+
+const MAX_DETAILS_LENGTH: usize = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    Spam,
+    Malware,
+    Phishing,
+    /// Someone's personal data.
+    Personal,
+    Illegal,
+    Other,
+}
+
+impl Reason {
+    pub const ALL: [Reason; 6] =
+        [Reason::Spam, Reason::Malware, Reason::Phishing, Reason::Personal, Reason::Illegal, Reason::Other];
+
+    fn parse(value: &str) -> Option<Reason> {
+        Reason::ALL.iter().copied().find(|reason| reason.name() == value)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::Spam => "spam",
+            Reason::Malware => "malware",
+            Reason::Phishing => "phishing",
+            Reason::Personal => "personal",
+            Reason::Illegal => "illegal",
+            Reason::Other => "other",
+        }
+    }
+
+    pub fn text_key(self) -> &'static str {
+        match self {
+            Reason::Spam => "report-reason-spam",
+            Reason::Malware => "report-reason-malware",
+            Reason::Phishing => "report-reason-phishing",
+            Reason::Personal => "report-reason-personal",
+            Reason::Illegal => "report-reason-illegal",
+            Reason::Other => "report-reason-other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    /// Unix time the report was made.
+    pub time: u64,
+    pub reason: Reason,
+    pub details: String,
+    pub ip_hash: String,
+}
+
+/// A report as posted, either by the form on the paste page or as JSON.
+#[derive(Deserialize, FromForm)]
+pub struct NewReport {
+    reason: String,
+    #[serde(default)]
+    details: String,
+}
+
+impl NewReport {
+    fn validate(self, ip_hash: String) -> Result<Report, String> {
+        let reason = Reason::parse(&self.reason).ok_or_else(|| {
+            let names: Vec<&str> = Reason::ALL.iter().map(|reason| reason.name()).collect();
+            format!("reason must be one of {}\n", names.join(", "))
+        })?;
+        let details = self.details.trim();
+        if details.chars().count() > MAX_DETAILS_LENGTH {
+            return Err(format!("details must have at most {} characters\n", MAX_DETAILS_LENGTH));
+        }
+        Ok(Report { time: unix_now(), reason, details: details.to_string(), ip_hash })
+    }
+}
+
+/// `report_threshold` from `Rocket.toml`; also serializes writes to the
+/// report files and limits reporting per address, IPv6 ones by /64.
+pub struct Reports {
+    threshold: usize,
+    lock: Mutex<()>,
+    limiter: RateLimiter,
+}
+
+pub enum ReportError {
+    NotFound,
+    Invalid(String),
+    AlreadyReported,
+    TooManyRequests(Duration),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ReportError {
+    fn from(error: io::Error) -> Self {
+        ReportError::Io(error)
+    }
+}
+
+impl<'r> Responder<'r> for ReportError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            ReportError::NotFound => Err(Status::NotFound),
+            ReportError::Invalid(message) => Response::build_from(message.respond_to(request)?)
+                .status(Status::BadRequest)
+                .ok(),
+            ReportError::AlreadyReported => Response::build_from("you already reported this paste\n".respond_to(request)?)
+                .status(Status::Conflict)
+                .ok(),
+            ReportError::TooManyRequests(wait) => Response::build()
+                .status(Status::TooManyRequests)
+                .raw_header("Retry-After", (wait.as_secs() + 1).to_string())
+                .ok(),
+            ReportError::Io(error) => Debug(error).respond_to(request),
+        }
+    }
+}
+
+fn reports_path(id: &str) -> String {
+    format!("upload/{id}.reports", id = id)
+}
+
+/// Reports of a paste, oldest first.
+pub fn load(id: &str) -> Vec<Report> {
+    fs::read_to_string(reports_path(id))
+        .map(|text| text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Forgets the reports of a paste once an admin has looked at it.
+pub fn dismiss(id: &str) {
+    let _ = fs::remove_file(reports_path(id));
+}
+
+pub struct ReportedPaste {
+    pub id: String,
+    pub meta: PasteMeta,
+    pub reports: Vec<Report>,
+}
+
+/// Pastes with reports, most reported first.
+pub fn queue() -> Vec<ReportedPaste> {
+    let mut queue: Vec<ReportedPaste> = fs::read_dir("upload")
+        .map(|entries| {
+            entries.filter_map(Result::ok)
+                .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".reports").map(str::to_string))
+                .filter(|id| valid_id(id))
+                .map(|id| ReportedPaste { meta: PasteMeta::load(&id), reports: load(&id), id })
+                .collect()
+        })
+        .unwrap_or_default();
+    queue.sort_by_key(|paste| std::cmp::Reverse(paste.reports.len()));
+    queue
+}
+
+/// Quarantines a paste pending review, unless it already is.
+fn hide(id: &str, hub: &EventHub) -> io::Result<()> {
+    let meta = PasteMeta::load(id);
+    if meta.quarantined {
+        return Ok(());
+    }
+    PasteMeta { quarantined: true, ..meta }.save(id)?;
+    hub.publish(id, PasteEvent::Deleted);
+    moderation::audit("reports", "quarantine", id)
+}
+
+fn file(id: &str, client: &ClientIp, new: NewReport, reports: &Reports, hub: &EventHub) -> Result<(), ReportError> {
+    if !meta::exists(id) {
+        return Err(ReportError::NotFound);
+    }
+    let ip_hash = client.0.as_ref().map(hash_network).unwrap_or_default();
+    reports.limiter.check(&ip_hash).map_err(ReportError::TooManyRequests)?;
+    let report = new.validate(ip_hash).map_err(ReportError::Invalid)?;
+
+    let _guard = reports.lock.lock().unwrap();
+    let previous = load(id);
+    if previous.iter().any(|previous| previous.ip_hash == report.ip_hash) {
+        return Err(ReportError::AlreadyReported);
+    }
+    let mut line = serde_json::to_string(&report).map_err(io::Error::from)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(reports_path(id))?.write_all(line.as_bytes())?;
+    if reports.threshold > 0 && previous.len() + 1 >= reports.threshold {
+        hide(id, hub)?;
+    }
+    Ok(())
+}
+
+#[post("/api/<id>/report", format = "json", data = "<report>")]
+fn report_json(
+    id: PasteID<'_>,
+    report: Json<NewReport>,
+    client: ClientIp,
+    reports: State<Reports>,
+    hub: State<EventHub>,
+) -> Result<status::Accepted<&'static str>, ReportError> {
+    file(&id.to_string(), &client, report.into_inner(), &reports, &hub)?;
+    Ok(status::Accepted(Some("thanks, a moderator will have a look\n")))
+}
+
+/// The report form on the paste page.
+#[post("/api/<id>/report", format = "form", data = "<report>", rank = 2)]
+fn report_form(
+    id: PasteID<'_>,
+    report: CsrfForm<NewReport>,
+    client: ClientIp,
+    reports: State<Reports>,
+    hub: State<EventHub>,
+) -> Result<Redirect, ReportError> {
+    let id = id.to_string();
+    file(&id, &client, report.into_inner(), &reports, &hub)?;
+    Ok(Redirect::to(format!("/{id}", id = id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![report_json, report_form]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("reports", |rocket| {
+        let threshold = rocket.config().get_int("report_threshold").unwrap_or(3).max(0) as usize;
+        let limiter = RateLimiter::new(10, Duration::from_secs(60));
+        Ok(rocket.manage(Reports { threshold, lock: Mutex::new(()), limiter }))
+    })
+}
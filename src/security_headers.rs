@@ -0,0 +1,72 @@
+//! Security headers for every response. HTML pages get a
+//! Content-Security-Policy that only runs scripts from our origin or with
+//! the page's nonce, so the few scripts still inline in the views carry
+//! `nonce=(nonce)`; the rest live in `static/js`. Raw pastes set their own,
+//! sandboxing policy (see `RawPaste`).
+//!
+//! `hsts_max_age` in `Rocket.toml` turns on `Strict-Transport-Security`;
+//! leave it at 0 unless the site is only reachable over HTTPS.
+
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+
+use crate::paste_id::PasteID;
+
+const NONCE_LENGTH: usize = 24;
+
+/// The CSP nonce of the request, the same for every script of a page.
+#[derive(Clone)]
+pub struct Nonce(pub String);
+
+impl Nonce {
+    fn of(request: &Request<'_>) -> Nonce {
+        request.local_cache(|| Nonce(PasteID::new(NONCE_LENGTH).to_string())).clone()
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Nonce {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Nonce::of(request))
+    }
+}
+
+fn page_policy(nonce: &str) -> String {
+    format!(
+        "default-src 'none'; script-src 'self' 'nonce-{nonce}'; \
+         style-src 'self'; img-src 'self' data:; \
+         connect-src 'self' ws: wss:; form-action 'self'; frame-ancestors 'none'; base-uri 'none'",
+        nonce = nonce
+    )
+}
+
+/// `hsts_max_age` from `Rocket.toml`, 0 when off.
+pub struct SecurityConfig {
+    hsts_max_age: u64,
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("security headers", |rocket| {
+        let hsts_max_age = rocket.config().get_int("hsts_max_age").unwrap_or(0).max(0) as u64;
+        Ok(rocket.manage(SecurityConfig { hsts_max_age }))
+    })
+}
+
+/// Sets the headers; a response's own Content-Security-Policy is kept.
+pub fn headers() -> impl Fairing {
+    AdHoc::on_response("security headers", |request, response| {
+        response.set_raw_header("X-Content-Type-Options", "nosniff");
+        response.set_raw_header("Referrer-Policy", "same-origin");
+        response.set_raw_header("Permissions-Policy", "camera=(), microphone=(), geolocation=(), interest-cohort=()");
+        if let Outcome::Success(config) = request.guard::<State<SecurityConfig>>() {
+            if config.hsts_max_age > 0 {
+                response.set_raw_header("Strict-Transport-Security", format!("max-age={}", config.hsts_max_age));
+            }
+        }
+        let is_page = response.content_type().map_or(false, |content_type| content_type.is_html());
+        if is_page && !response.headers().contains("Content-Security-Policy") {
+            response.set_raw_header("Content-Security-Policy", page_policy(&Nonce::of(request).0));
+        }
+    })
+}
@@ -0,0 +1,55 @@
+//! sprunge.us / ix.io compatible uploads:
+//!
+//!     command | curl -F 'sprunge=<-' https://copy.red
+//!     command | curl -F 'f:1=<-' https://copy.red
+//!
+//! The response is the plain-text URL of the paste; appending `?<lang>` to it
+//! (e.g. `https://copy.red/abc?py`) shows the paste highlighted.
+
+use std::io;
+
+use multipart::server::Multipart;
+use rocket::data::Data;
+use rocket::http::ContentType;
+use rocket::Route;
+
+use crate::api_key::Uploader;
+use crate::options::UploadOptions;
+use crate::rate_limit::UploadLimit;
+use crate::{store_paste, Created, UploadError, HOST};
+
+/// `sprunge` for sprunge.us, `f:<n>` for ix.io.
+fn is_paste_field(name: &str) -> bool {
+    name == "sprunge" || (name.starts_with("f:") && name[2..].chars().all(|c| c.is_ascii_digit()))
+}
+
+#[post("/", format = "multipart/form-data", data = "<data>", rank = 2)]
+fn upload_multipart(
+    _limit: UploadLimit,
+    uploader: Uploader,
+    content_type: &ContentType,
+    data: Data,
+    options: Result<UploadOptions, String>,
+) -> Result<Created, UploadError> {
+    let mut options = options.map_err(UploadError::BadRequest)?;
+    uploader.attribute(&mut options);
+    let boundary = content_type.params()
+        .find(|&(key, _)| key == "boundary")
+        .map(|(_, value)| value.to_string())
+        .ok_or_else(|| UploadError::BadRequest("missing multipart boundary\n".into()))?;
+
+    let mut multipart = Multipart::with_body(data.open(), boundary);
+    while let Some(mut field) = multipart.read_entry()? {
+        if !is_paste_field(&field.headers.name) {
+            io::copy(&mut field.data, &mut io::sink())?;
+            continue;
+        }
+        let (id, token) = store_paste(&mut field.data, &options)?;
+        return Ok(Created { url: format!("{host}/{id}\n", host = HOST, id = id), token });
+    }
+    Err(UploadError::BadRequest("expected a `sprunge` or `f:1` field\n".into()))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![upload_multipart]
+}
@@ -0,0 +1,127 @@
+//! termbin-style uploads over plain TCP, for machines without curl:
+//!
+//!     make 2>&1 | nc copy.red 9999
+//!
+//! The listener reads until EOF, or until the client has been quiet for
+//! `tcp_timeout` seconds, stores the data like `POST /api/paste`, writes back
+//! the URL and closes. It is disabled unless `tcp_port` is set.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rocket::fairing::{AdHoc, Fairing};
+
+use crate::moderation::{self, BanKind};
+use crate::options::UploadOptions;
+use crate::rate_limit::RateLimiter;
+use crate::HOST;
+
+const MAX_CONNECTIONS: usize = 64;
+/// A connection is cut off after this long, however slowly data trickles in.
+const MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// `tcp_port`, `tcp_max_size` (bytes), `tcp_timeout` (seconds) and
+/// `tcp_rate` (pastes per minute and address) from `Rocket.toml`.
+pub struct TcpConfig {
+    pub port: u16,
+    pub max_size: u64,
+    pub timeout: Duration,
+    pub rate: u32,
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut
+}
+
+/// Reads up to `max_size + 1` bytes, so the caller can tell an oversized
+/// paste apart. An idle client counts as finished.
+fn read_paste(stream: &mut TcpStream, max_size: u64) -> io::Result<Vec<u8>> {
+    let started = Instant::now();
+    let mut data = Vec::new();
+    let mut buf = [0; 8192];
+    while (data.len() as u64) <= max_size && started.elapsed() < MAX_DURATION {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(ref e) if is_timeout(e) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(data)
+}
+
+fn serve(mut stream: TcpStream, config: &TcpConfig, limiter: &RateLimiter) -> io::Result<()> {
+    let address = stream.peer_addr()?.ip();
+    if moderation::is_banned(BanKind::Ip, &moderation::hash_ip(&address)) {
+        return writeln!(stream, "uploads from this address are not allowed");
+    }
+    if let Err(wait) = limiter.check(&address.to_string()) {
+        return writeln!(stream, "too many pastes, try again in {}s", wait.as_secs() + 1);
+    }
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    let data = read_paste(&mut stream, config.max_size)?;
+    let _ = stream.shutdown(Shutdown::Read);
+    if data.is_empty() {
+        return writeln!(stream, "nothing to paste");
+    }
+    if data.len() as u64 > config.max_size {
+        return writeln!(stream, "paste is larger than {} bytes", config.max_size);
+    }
+    let mut options = UploadOptions::default();
+    options.meta.ip_hash = Some(moderation::hash_ip(&address));
+    let (id, _) = match crate::store_paste(&data[..], &options) {
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return write!(stream, "{}", e),
+        stored => stored?,
+    };
+    writeln!(stream, "{host}/api/{id}", host = HOST, id = id)
+}
+
+pub fn listen(listener: TcpListener, config: TcpConfig) {
+    let config = Arc::new(config);
+    let limiter = Arc::new(RateLimiter::new(config.rate, Duration::from_secs(60)));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().filter_map(Result::ok) {
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let (config, limiter, connections) = (config.clone(), limiter.clone(), connections.clone());
+        thread::spawn(move || {
+            let _ = serve(stream, &config, &limiter);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Starts the listener next to rocket's, on the same address.
+pub fn listener() -> impl Fairing {
+    AdHoc::on_launch("tcp listener", |rocket| {
+        let config = rocket.config();
+        let port = config.get_int("tcp_port").unwrap_or(0);
+        if port <= 0 {
+            return;
+        }
+        if !config.get_bool("anonymous_uploads").unwrap_or(true) {
+            eprintln!("tcp listener disabled, since anonymous uploads are off");
+            return;
+        }
+        let tcp = TcpConfig {
+            port: port as u16,
+            max_size: config.get_int("tcp_max_size").unwrap_or(1 << 20).max(0) as u64,
+            timeout: Duration::from_secs(config.get_int("tcp_timeout").unwrap_or(2).max(1) as u64),
+            rate: config.get_int("tcp_rate").unwrap_or(10).max(1) as u32,
+        };
+        match TcpListener::bind((config.address.as_str(), tcp.port)) {
+            Ok(listener) => {
+                thread::spawn(move || listen(listener, tcp));
+            }
+            Err(e) => eprintln!("tcp listener on port {} failed: {}", tcp.port, e),
+        }
+    })
+}
@@ -1,6 +1,6 @@
 use super::{rocket, index};
 use rocket::local::blocking::Client;
-use rocket::http::{Status, ContentType};
+use rocket::http::{Status, ContentType, Header};
 
 fn extract_id(from: &str) -> Option<String> {
     from.rfind('/').map(|i| &from[(i + 1)..]).map(|s| s.trim_end().to_string())
@@ -68,3 +68,779 @@ fn pasting() {
     assert_eq!(download_paste(&client, &id_1), body_1);
     assert_eq!(download_paste(&client, &id_2), body_2);
 }
+
+#[test]
+fn resumable_upload() {
+    let client = Client::new(rocket()).unwrap();
+    let tus = Header::new("Tus-Resumable", "1.0.0");
+    let offset_stream = ContentType::new("application", "offset+octet-stream");
+
+    let response = client.post("/api/tus")
+        .header(tus.clone())
+        .header(Header::new("Upload-Length", "11"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let location = response.headers().get_one("Location").unwrap().to_string();
+
+    // Send the first half, then ask the server where to resume.
+    let response = client.patch(&location)
+        .header(tus.clone())
+        .header(offset_stream.clone())
+        .header(Header::new("Upload-Offset", "0"))
+        .body("Hello")
+        .dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    let response = client.head(&location).header(tus.clone()).dispatch();
+    assert_eq!(response.headers().get_one("Upload-Offset"), Some("5"));
+
+    // A stale offset is rejected.
+    let response = client.patch(&location)
+        .header(tus.clone())
+        .header(offset_stream.clone())
+        .header(Header::new("Upload-Offset", "0"))
+        .body("Hello")
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+
+    let response = client.patch(&location)
+        .header(tus.clone())
+        .header(offset_stream.clone())
+        .header(Header::new("Upload-Offset", "5"))
+        .body(", tus!")
+        .dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    let url = response.headers().get_one("X-Paste-Url").unwrap().to_string();
+    let token = Header::new("X-Owner-Token", response.headers().get_one("X-Owner-Token").unwrap().to_string());
+    let id = extract_id(&url).unwrap();
+    let response = client.get(format!("/api/{}", id)).dispatch();
+    assert_eq!(response.into_string(), Some("Hello, tus!".into()));
+    assert!(super::meta::PasteMeta::load(&id).created.is_some());
+
+    // A finished upload takes no more data.
+    let response = client.patch(&location)
+        .header(tus)
+        .header(offset_stream)
+        .header(Header::new("Upload-Offset", "11"))
+        .body("!")
+        .dispatch();
+    assert_eq!(response.status(), Status::Gone);
+
+    // The uploader owns the paste like any other.
+    assert_eq!(client.delete(format!("/api/{}", id)).header(token).dispatch().status(), Status::NoContent);
+}
+
+#[test]
+fn upload_options() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.post("/api/paste?colour=red").body("x").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response.into_string().unwrap().contains("unknown option `colour`"));
+
+    let response = client.post("/api/paste").header(Header::new("X-Paste-Expiry", "soon")).body("x").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // A burn-after-read paste can be read exactly once.
+    let response = client.post("/api/paste?burn&id_length=8&filename=notes.txt").body("secret").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    assert_eq!(id.len(), 8);
+    let response = client.get(format!("/api/{}", id)).dispatch();
+    assert_eq!(response.headers().get_one("Content-Disposition"), Some("inline; filename=\"notes.txt\""));
+    assert_eq!(response.into_string(), Some("secret".into()));
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn put_upload_keeps_filename() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.put("/build.json").body("{\"ok\": true}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let url = response.into_string().unwrap();
+    assert!(url.trim_end().ends_with("/build.json"));
+
+    let path = &url.trim_end()[url.find("/api/").unwrap()..];
+    let response = client.get(path).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    assert_eq!(response.into_string(), Some("{\"ok\": true}".into()));
+
+    // Markup is never served with a type the browser would render.
+    let url = client.put("/page.html").body("<script></script>").dispatch().into_string().unwrap();
+    let id = url.trim_end().rsplit('/').nth(1).unwrap().to_string();
+    let response = client.get(format!("/api/{}", id)).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::Plain));
+}
+
+#[test]
+fn hastebin_api() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.post("/documents").body("haste").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body = response.into_string().unwrap();
+    let key = body.trim_start_matches("{\"key\":\"").trim_end_matches("\"}").to_string();
+
+    let response = client.get(format!("/documents/{}", key)).dispatch();
+    assert_eq!(response.into_string(), Some(format!("{{\"key\":\"{}\",\"data\":\"haste\"}}", key)));
+    assert_eq!(client.get(format!("/raw/{}", key)).dispatch().into_string(), Some("haste".into()));
+    assert_eq!(client.get(format!("/api/{}", key)).dispatch().into_string(), Some("haste".into()));
+    assert_eq!(client.get("/documents/missing0").dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn sprunge_upload() {
+    let client = Client::new(rocket()).unwrap();
+    let multipart = ContentType::with_params("multipart", "form-data", ("boundary", "X-BOUNDARY"));
+
+    for field in &["sprunge", "f:1"] {
+        let body = format!("--X-BOUNDARY\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\nfn main() {{}}\r\n--X-BOUNDARY--\r\n", field);
+        let response = client.post("/").header(multipart.clone()).body(body).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let id = extract_id(&response.into_string().unwrap()).unwrap();
+        assert_eq!(client.get(format!("/api/{}", id)).dispatch().into_string(), Some("fn main() {}".into()));
+
+        let page = client.get(format!("/{}?rust", id)).dispatch().into_string().unwrap();
+        assert!(page.contains("class=\"language-rust\""));
+    }
+
+    let body = "--X-BOUNDARY\r\nContent-Disposition: form-data; name=\"other\"\r\n\r\nx\r\n--X-BOUNDARY--\r\n";
+    let response = client.post("/").header(multipart).body(body).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn append_to_paste() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.post("/api/paste").body("line 1\n").dispatch();
+    let token = response.headers().get_one("X-Owner-Token").unwrap().to_string();
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    let append = format!("/api/{}/append", id);
+
+    assert_eq!(client.post(&append).body("x").dispatch().status(), Status::Unauthorized);
+    let response = client.post(&append).header(Header::new("X-Owner-Token", "wrong")).body("x").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = client.post(&append).header(Header::new("X-Owner-Token", token)).body("line 2\n").dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(download_paste(&client, &format!("api/{}", id)), "line 1\nline 2\n");
+}
+
+#[test]
+fn edit_and_delete_paste() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.post("/api/paste").body("draft").dispatch();
+    let token = Header::new("X-Owner-Token", response.headers().get_one("X-Owner-Token").unwrap().to_string());
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    let path = format!("/api/{}", id);
+
+    assert_eq!(client.put(&path).body("final").dispatch().status(), Status::Unauthorized);
+    assert_eq!(client.put(&path).header(token.clone()).body("final").dispatch().status(), Status::NoContent);
+    assert_eq!(download_paste(&client, &path[1..]), "final");
+
+    assert_eq!(client.delete(&path).header(token).dispatch().status(), Status::NoContent);
+    assert_eq!(client.get(&path).dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn paste_chat_thread() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.post("/api/paste").body("fn main() {}\n").dispatch();
+    let token = Header::new("X-Owner-Token", response.headers().get_one("X-Owner-Token").unwrap().to_string());
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    let chat = format!("/api/{}/chat", id);
+
+    let response = client.post(&chat).header(ContentType::JSON)
+        .body(r#"{"nick": "ann", "line": 1, "text": "needs a body"}"#).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let response = client.post(&chat).header(ContentType::Form).body("nick=&line=&text=lgtm").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let form = format!("{}&nick=&line=&text=lgtm", csrf_field(&client, &format!("/{}", id)));
+    let response = client.post(&chat).header(ContentType::Form).body(form).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    let response = client.post(&chat).header(ContentType::JSON).body(r#"{"text": " "}"#).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let thread = client.get(&chat).dispatch().into_string().unwrap();
+    assert!(thread.find("needs a body").unwrap() < thread.find("lgtm").unwrap());
+    assert!(thread.contains(r#""nick":"anonymous""#));
+    assert!(client.get(format!("/{}", id)).dispatch().into_string().unwrap().contains("needs a body"));
+
+    // The thread goes away with the paste.
+    client.delete(format!("/api/{}", id)).header(token).dispatch();
+    assert_eq!(client.get(&chat).dispatch().status(), Status::NotFound);
+    assert!(!std::path::Path::new(&format!("upload/{}.chat", id)).exists());
+}
+
+#[test]
+fn paste_qr_codes() {
+    let client = Client::new(rocket()).unwrap();
+    let response = client.post("/api/paste").body("scan me").dispatch();
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+
+    let response = client.get(format!("/api/{}/qr.svg", id)).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::SVG));
+    assert!(response.into_string().unwrap().contains("<svg"));
+
+    let response = client.get(format!("/api/{}/qr.png", id)).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert!(response.into_bytes().unwrap().starts_with(b"\x89PNG"));
+
+    assert!(client.get(format!("/{}", id)).dispatch().into_string().unwrap().contains("id=\"qrcode\""));
+    assert_eq!(client.get("/api/nope/qr.svg").dispatch().status(), Status::NotFound);
+
+    // A file name that collides with these routes is left out of the URL.
+    let response = client.put("/qr.png").body("not a picture").dispatch();
+    assert!(!response.into_string().unwrap().contains("qr.png"));
+}
+
+#[test]
+fn termbin_upload() {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::time::Duration;
+    use super::termbin::{listen, TcpConfig};
+
+    let client = Client::new(rocket()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let config = TcpConfig { port: address.port(), max_size: 16, timeout: Duration::from_secs(1), rate: 2 };
+    std::thread::spawn(move || listen(listener, config));
+
+    let nc = |data: &[u8]| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(data).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        reply
+    };
+    let id = extract_id(&nc(b"from netcat\n")).unwrap();
+    assert_eq!(download_paste(&client, &format!("api/{}", id)), "from netcat\n");
+    assert!(nc(b"more than sixteen bytes").contains("larger than 16 bytes"));
+    assert!(nc(b"third").starts_with("too many pastes"));
+}
+
+#[test]
+fn encrypted_paste() {
+    let client = Client::new(rocket()).unwrap();
+    let envelope = r#"{"v":1,"alg":"AES-GCM","iv":"AAAAAAAAAAAAAAAA","ct":"AAAAAAAAAAAAAAAAAAAAAA=="}"#;
+
+    // Like the paste form they come from, they need the proof of work.
+    assert_eq!(client.post("/api/paste?encrypted").body(envelope).dispatch().status(), Status::Forbidden);
+    let solved = || solved_form_url(&client, "/", "/api/paste");
+
+    let response = client.post(format!("{}&encrypted", solved())).body("plain text").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(!response.into_string().unwrap().contains("plain text"));
+    let bad_iv = envelope.replace("AAAAAAAAAAAAAAAA", "AAAA");
+    assert_eq!(client.post(format!("{}&encrypted", solved())).body(bad_iv).dispatch().status(), Status::BadRequest);
+
+    let response = client.post(solved()).header(Header::new("X-Paste-Encrypted", "true")).body(envelope).dispatch();
+    let token = Header::new("X-Owner-Token", response.headers().get_one("X-Owner-Token").unwrap().to_string());
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+
+    let response = client.get(format!("/api/{}", id)).dispatch();
+    assert_eq!(response.headers().get_one("X-Paste-Encrypted"), Some("true"));
+    assert_eq!(response.into_string().unwrap(), envelope);
+    let page = client.get(format!("/{}", id)).dispatch().into_string().unwrap();
+    assert!(page.contains("id=\"encrypted\""));
+    assert!(!page.contains("id=\"qrcode\""));
+
+    let append = client.post(format!("/api/{}/append", id)).header(token.clone()).body("x").dispatch();
+    assert_eq!(append.status(), Status::Conflict);
+    let edit = client.put(format!("/api/{}", id)).header(token).body("plain text").dispatch();
+    assert_eq!(edit.status(), Status::BadRequest);
+}
+
+#[test]
+fn password_protected_paste() {
+    let client = Client::new(rocket()).unwrap();
+    let password = Header::new("X-Paste-Password", "hunter2");
+
+    let response = client.post("/api/paste?password=hunter2").body("secret").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = client.post("/api/paste").header(password.clone()).body("secret").dispatch();
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    let path = format!("/api/{}", id);
+
+    let response = client.get(&path).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(response.headers().get_one("WWW-Authenticate").unwrap().starts_with("Basic"));
+    let page = client.get(format!("/{}", id)).dispatch().into_string().unwrap();
+    assert!(page.contains(&format!("/{}/unlock", id)) && !page.contains("secret"));
+
+    // Basic auth, with any user name; "user:hunter2" in base64.
+    let basic = Header::new("Authorization", "Basic dXNlcjpodW50ZXIy");
+    assert_eq!(client.get(&path).header(basic).dispatch().into_string(), Some("secret".into()));
+    assert_eq!(client.get(&path).header(password).dispatch().into_string(), Some("secret".into()));
+    let page = client.post(format!("/{}/unlock", id)).header(ContentType::Form).body("password=hunter2").dispatch();
+    assert!(page.into_string().unwrap().contains("secret"));
+
+    // Wrong guesses are limited per paste and address, after which even the
+    // right password has to wait there; other readers are not locked out.
+    let guesser: std::net::SocketAddr = "192.0.2.70:4000".parse().unwrap();
+    for _ in 0..5 {
+        let response = client.get(&path).remote(guesser).header(Header::new("X-Paste-Password", "guess")).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+    let response = client.get(&path).remote(guesser).header(Header::new("X-Paste-Password", "hunter2")).dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.headers().get_one("Retry-After").is_some());
+    let reader: std::net::SocketAddr = "192.0.2.71:4000".parse().unwrap();
+    let response = client.get(&path).remote(reader).header(Header::new("X-Paste-Password", "hunter2")).dispatch();
+    assert_eq!(response.into_string(), Some("secret".into()));
+}
+
+#[test]
+fn event_hub_fan_out() {
+    use super::events::{EventHub, PasteEvent};
+
+    let hub = EventHub::default();
+    let first = hub.subscribe("abc");
+    let second = hub.subscribe("abc");
+    let other = hub.subscribe("xyz");
+    drop(second);
+
+    hub.publish("abc", PasteEvent::Appended { text: "more".into() });
+    match first.try_recv() {
+        Ok(PasteEvent::Appended { text }) => assert_eq!(text, "more"),
+        event => panic!("unexpected {:?}", event),
+    }
+    assert!(other.try_recv().is_err());
+}
+
+#[test]
+fn event_stream_limits() {
+    let client = Client::new(rocket()).unwrap();
+    let response = client.post("/api/paste").body("watched").dispatch();
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    let events = format!("/api/{}/events", id);
+    let address: std::net::SocketAddr = "192.0.2.50:4000".parse().unwrap();
+
+    // The page only streams once asked to.
+    let page = client.get(format!("/{}", id)).dispatch().into_string().unwrap();
+    assert!(page.contains("id=\"follow\""));
+
+    // Two streams per address; open ones hold their slot.
+    let first = client.get(&events).remote(address).dispatch();
+    let second = client.get(format!("/api/{}?follow=1", id)).remote(address).dispatch();
+    assert_eq!(first.status(), Status::Ok);
+    assert_eq!(second.status(), Status::Ok);
+    assert_eq!(client.get(&events).remote(address).dispatch().status(), Status::TooManyRequests);
+    drop(first);
+    assert_eq!(client.get(&events).remote(address).dispatch().status(), Status::Ok);
+}
+
+#[test]
+fn clipboard_channel() {
+    let client = Client::new(rocket()).unwrap();
+    let name = format!("/c/test-{}", super::PasteID::new(8));
+
+    // The first push claims the channel and hands out its token.
+    let response = client.post(&name).body("first").dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let token = Header::new("X-Channel-Token", response.headers().get_one("X-Channel-Token").unwrap().to_string());
+    let stored = std::fs::read_to_string(format!("upload/.channels/{}/token", &name[3..])).unwrap();
+    assert!(!stored.contains(token.value()));
+
+    assert_eq!(client.post(&name).body("stolen").dispatch().status(), Status::Forbidden);
+    assert_eq!(client.get(&name).header(Header::new("X-Channel-Token", "wrong")).dispatch().status(), Status::Forbidden);
+
+    let response = client.post(&name).header(token.clone()).body("second").dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    let response = client.get(&name).header(token.clone()).dispatch();
+    assert_eq!(response.into_string(), Some("second".into()));
+
+    let history = client.get(format!("{}/history", name)).header(token).dispatch().into_string().unwrap();
+    assert!(history.find("second").unwrap() < history.find("first").unwrap());
+}
+
+#[test]
+fn channel_push_reaches_websocket_subscribers() {
+    use std::sync::Arc;
+    use super::channel::Channels;
+
+    let client = Client::new(rocket()).unwrap();
+    let name = format!("test-{}", super::PasteID::new(8));
+    let response = client.post(format!("/c/{}", name)).body("first").dispatch();
+    let token = Header::new("X-Channel-Token", response.headers().get_one("X-Channel-Token").unwrap().to_string());
+
+    // The websocket listener relays whatever a subscription receives.
+    let entries = client.rocket().state::<Arc<Channels>>().unwrap().subscribe(&name);
+    client.post(format!("/c/{}", name)).header(token).body("from http").dispatch();
+    assert_eq!(entries.try_recv().unwrap().text, "from http");
+
+    let endpoint = client.get("/api/ws").header(Header::new("Host", "copy.red")).dispatch();
+    assert_eq!(endpoint.status(), Status::Ok);
+    assert!(endpoint.into_string().unwrap().contains("\"url\""));
+
+    // HTTPS pages, here through a trusted proxy, stay on the same origin.
+    let proxy: std::net::SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let endpoint = client.get("/api/ws")
+        .remote(proxy)
+        .header(Header::new("Host", "copy.red"))
+        .header(Header::new("X-Forwarded-Proto", "https"))
+        .dispatch();
+    assert!(endpoint.into_string().unwrap().contains("wss://copy.red/ws"));
+}
+
+#[test]
+fn device_pairing() {
+    let client = Client::new(rocket()).unwrap();
+
+    let response = client.post("/api/pair").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let code = &body[body.find("\"code\":\"").unwrap() + 8..][..6];
+
+    // The second device gets the same channel, and the code is single use.
+    let response = client.post(format!("/api/pair/{}", code)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let paired = response.into_string().unwrap();
+    let channel = &paired[paired.find("\"channel\":\"").unwrap()..];
+    assert!(body.contains(&channel[..channel.find(',').unwrap()]));
+    assert_eq!(client.post(format!("/api/pair/{}", code)).dispatch().status(), Status::NotFound);
+
+    // Guessing is cut off quickly.
+    let statuses: Vec<Status> = (0..10).map(|_| client.post("/api/pair/000000").dispatch().status()).collect();
+    assert!(statuses.contains(&Status::TooManyRequests));
+    // But only for the guessing address.
+    let other: std::net::SocketAddr = "192.0.2.60:4000".parse().unwrap();
+    assert_eq!(client.post("/api/pair/000000").remote(other).dispatch().status(), Status::NotFound);
+
+    // An IPv6 client can't move on to the next address in its /64.
+    for _ in 0..5 {
+        client.post("/api/pair/000000").remote("[2001:db8:0:3::1]:4000".parse().unwrap()).dispatch();
+    }
+    let neighbour: std::net::SocketAddr = "[2001:db8:0:3::2]:4000".parse().unwrap();
+    assert_eq!(client.post("/api/pair/000000").remote(neighbour).dispatch().status(), Status::TooManyRequests);
+
+    // Nor can many networks together guess more than a few per code.
+    let statuses: Vec<Status> = (0..200)
+        .map(|i| client.post("/api/pair/000000").remote(format!("198.18.{}.1:4000", i).parse().unwrap()).dispatch().status())
+        .collect();
+    assert!(statuses.contains(&Status::TooManyRequests));
+}
+
+#[test]
+fn rate_limited_not_found() {
+    let client = Client::new(rocket()).unwrap();
+    let proxy: std::net::SocketAddr = "127.0.0.1:40000".parse().unwrap();
+    let forwarded = |ip: &str| Header::new("X-Forwarded-For", format!("10.9.9.9, {}", ip));
+
+    // Behind a trusted proxy, the client is the last forwarded address.
+    for _ in 0..60 {
+        let response = client.get("/api/missing0").remote(proxy).header(forwarded("203.0.113.7")).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+    let response = client.get("/api/missing0").remote(proxy).header(forwarded("203.0.113.7")).dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.headers().get_one("Retry-After").unwrap().parse::<u64>().is_ok());
+    let response = client.get("/api/missing0").remote(proxy).header(forwarded("203.0.113.8")).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // Other peers can't pick their address with the header.
+    let peer = "198.51.100.1:40000".parse().unwrap();
+    let response = client.get("/api/missing0").remote(peer).header(forwarded("203.0.113.8")).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // Paste pages count too, and an IPv6 client is its whole /64.
+    for i in 0..60 {
+        let peer = format!("[2001:db8:0:1::{:x}]:40000", i + 1).parse().unwrap();
+        let response = client.get("/missing1").remote(peer).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+    let peer = "[2001:db8:0:1:ffff::1]:40000".parse().unwrap();
+    assert_eq!(client.get("/missing1").remote(peer).dispatch().status(), Status::TooManyRequests);
+    let peer = "[2001:db8:0:2::1]:40000".parse().unwrap();
+    assert_eq!(client.get("/missing1").remote(peer).dispatch().status(), Status::NotFound);
+}
+
+/// The hidden `csrf` field of the forms on `page`, as `csrf=<token>`.
+fn csrf_field(client: &Client, page: &str) -> String {
+    let page = client.get(page).dispatch().into_string().unwrap();
+    let field = "name=\"csrf\" value=\"";
+    let start = page.find(field).unwrap() + field.len();
+    format!("csrf={}", &page[start..][..page[start..].find('"').unwrap()])
+}
+
+/// Solves the proof of work of the form on `page`, returning the URL to
+/// post it to at `path`.
+fn solved_form_url(client: &Client, page: &str, path: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    let page = client.get(page).dispatch().into_string().unwrap();
+    let start = page.find("data-challenge=\"").unwrap() + 16;
+    let challenge = &page[start..][..page[start..].find('"').unwrap()];
+    let bits: u32 = challenge.split('.').next().unwrap().parse().unwrap();
+    let solved = |counter: u32| {
+        let hash = Sha256::digest(format!("{}.{}", challenge, counter).as_bytes());
+        hash.iter().map(|byte| byte.leading_zeros()).scan(true, |zeros, n| {
+            let bits = if *zeros { n } else { 0 };
+            *zeros = *zeros && n == 8;
+            Some(bits)
+        }).sum::<u32>() >= bits
+    };
+    let counter = (0..).find(|&counter| solved(counter)).unwrap();
+    format!("{}?pow={}.{}", path, challenge, counter)
+}
+
+#[test]
+fn paste_form_proof_of_work() {
+    let client = Client::new(rocket()).unwrap();
+    let form = format!("{}&paste_text=hello&encrypt=false", csrf_field(&client, "/"));
+    let response = client.post("/").header(ContentType::Form).body(&form).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // The CSRF token goes in the form, not the URL.
+    let url = solved_form_url(&client, "/", "/");
+    let response = client.post(&url).header(ContentType::Form).body("paste_text=hello&encrypt=false").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert!(!client.get("/").dispatch().into_string().unwrap().contains("csrf="));
+
+    let url = solved_form_url(&client, "/", "/");
+    let response = client.post(&url).header(ContentType::Form).body(&form).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+
+    // Each challenge is good for one paste.
+    let response = client.post(&url).header(ContentType::Form).body(&form).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[test]
+fn api_keys() {
+    use super::api_key::{self, Scope};
+
+    let client = Client::new(rocket()).unwrap();
+    let (_, admin) = api_key::mint("test admin", &[Scope::Admin]).unwrap();
+    let bearer = |token: &str| Header::new("Authorization", format!("Bearer {}", token));
+
+    let new_key = r#"{"name":"ann","scopes":["upload","delete"]}"#;
+    let response = client.post("/api/keys").header(ContentType::JSON).body(new_key).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client.post("/api/keys").header(ContentType::JSON).header(bearer(&admin)).body(new_key).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let body = response.into_string().unwrap();
+    let start = body.find("\"token\":\"").unwrap() + 9;
+    let token = body[start..][..body[start..].find('"').unwrap()].to_string();
+    let key_id = token.split('_').nth(1).unwrap().to_string();
+    assert_eq!(client.get("/api/keys").header(bearer(&token)).dispatch().status(), Status::Forbidden);
+
+    // Pastes are attributed to the key, which may delete them.
+    let response = client.post("/api/paste").header(bearer(&token)).body("team notes").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    assert_eq!(super::meta::PasteMeta::load(&id).api_key, Some(key_id.clone()));
+    let response = client.post("/api/paste").header(bearer("pb_nope_nope")).body("spoofed").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(client.delete(format!("/api/{}", id)).header(bearer(&token)).dispatch().status(), Status::NoContent);
+
+    let response = client.delete(format!("/api/keys/{}", key_id)).header(bearer(&admin)).dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    let response = client.post("/api/paste").header(bearer(&token)).body("revoked").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn user_accounts() {
+    let client = Client::new(rocket()).unwrap();
+    let name = format!("user-{}", super::PasteID::new(8).to_string().to_lowercase());
+    let credentials = format!("name={}&password=correct-horse", name);
+    let post_form = |path: &str, page: &str, body: &str| {
+        let body = format!("{}&{}", csrf_field(&client, page), body);
+        client.post(path.to_string()).header(ContentType::Form).body(body).dispatch()
+    };
+    let register = |body: &str| {
+        let url = solved_form_url(&client, "/register", "/register");
+        let body = format!("{}&{}", csrf_field(&client, "/register"), body);
+        client.post(url).header(ContentType::Form).body(body).dispatch()
+    };
+
+    assert_eq!(client.get("/my").dispatch().headers().get_one("Location"), Some("/login"));
+    let response = register(&format!("name={}&password=short", name));
+    assert!(response.into_string().unwrap().contains("at least 8 characters"));
+    let url = solved_form_url(&client, "/register", "/register");
+    let response = client.post(url).header(ContentType::Form).body(&credentials).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = post_form("/register", "/register", &credentials);
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = register(&credentials);
+    assert_eq!(response.headers().get_one("Location"), Some("/my"));
+    let set_cookie = response.headers().get("Set-Cookie").find(|cookie| cookie.starts_with("session=")).unwrap();
+    let session = Header::new("Cookie", set_cookie.split(';').next().unwrap().to_string());
+    let response = register(&credentials);
+    assert!(response.into_string().unwrap().contains("taken"));
+
+    // Uploads from the form or its script are attributed to the logged-in user.
+    let response = client.post("/api/paste?title=release%20notes").body("for my eyes").dispatch();
+    let other = extract_id(&response.into_string().unwrap()).unwrap();
+    assert_eq!(super::meta::PasteMeta::load(&other).user, Some(name.clone()));
+    let url = solved_form_url(&client, "/", "/");
+    let form = format!("{}&paste_text=mine&encrypt=false", csrf_field(&client, "/"));
+    let response = client.post(&url).header(ContentType::Form).body(form).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    let id = response.headers().get_one("Location").unwrap().trim_start_matches('/').to_string();
+    assert_eq!(super::meta::PasteMeta::load(&id).user, Some(name.clone()));
+    let page = client.get("/my").dispatch().into_string().unwrap();
+    assert!(page.contains(&format!("/{}", id)) && page.contains(&format!("/{}\"", other)));
+    assert!(!client.get("/my?q=nothing-like-it").dispatch().into_string().unwrap().contains(&format!("/my/{}/delete", id)));
+
+    let response = post_form(&format!("/my/{}/delete", id), "/my", "");
+    assert_eq!(response.headers().get_one("Location"), Some("/my"));
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::NotFound);
+
+    // Logging out ends the session, not just the cookie.
+    post_form("/logout", "/my", "");
+    let response = client.get("/my").header(session).dispatch();
+    assert_eq!(response.headers().get_one("Location"), Some("/login"));
+
+    // Wrong passwords lock out the guessing address only.
+    let guesser = std::net::SocketAddr::from(([192, 0, 2, 80], 4000));
+    let log_in = |body: &str| {
+        let body = format!("{}&{}", csrf_field(&client, "/login"), body);
+        client.post("/login").header(ContentType::Form).remote(guesser).body(body).dispatch()
+    };
+    for _ in 0..6 {
+        assert_eq!(log_in(&format!("name={}&password=wrong-horse", name)).status(), Status::Unauthorized);
+    }
+    let response = log_in(&credentials);
+    assert!(response.into_string().unwrap().contains("Too many attempts"));
+    let response = post_form("/login", "/login", &credentials);
+    assert_eq!(response.headers().get_one("Location"), Some("/my"));
+}
+
+#[test]
+fn security_headers() {
+    let client = Client::new(rocket()).unwrap();
+    let response = client.get("/").dispatch();
+    assert_eq!(response.headers().get_one("X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(response.headers().get_one("Referrer-Policy"), Some("same-origin"));
+    let policy = response.headers().get_one("Content-Security-Policy").unwrap().to_string();
+    let nonce = policy.split("'nonce-").nth(1).and_then(|rest| rest.split('\'').next()).unwrap().to_string();
+    let page = response.into_string().unwrap();
+    assert!(page.contains(&format!("nonce=\"{}\"", nonce)));
+    assert!(!page.contains("onclick=") && !page.contains("onsubmit=") && !page.contains("x-data"));
+    assert!(page.contains(&super::assets::url("js/clipboard.js")));
+    // Third-party CSS comes from our own static/vendor, fingerprinted.
+    let tailwind = super::assets::url("vendor/tailwind.min.css");
+    assert_ne!(tailwind, "/static/vendor/tailwind.min.css");
+    assert!(!policy.contains("https:") && page.contains(&tailwind));
+
+    let response = client.post("/api/paste").body("<script>alert(1)</script>").dispatch();
+    let url = response.into_string().unwrap();
+    let response = client.get(&url.trim_end()[url.find("/api/").unwrap()..]).dispatch();
+    assert_eq!(response.headers().get_one("Content-Security-Policy"), Some("sandbox; default-src 'none'"));
+}
+
+#[test]
+fn fingerprinted_static_files() {
+    let client = Client::new(rocket()).unwrap();
+    let url = super::assets::url("js/clipboard.js");
+    assert_ne!(url, "/static/js/clipboard.js");
+    let response = client.get(&url).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::JavaScript));
+    assert!(response.headers().get_one("Cache-Control").unwrap().contains("immutable"));
+    assert_eq!(response.headers().get_one("Content-Encoding"), None);
+
+    assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+
+    let response = client.get("/static/js/clipboard.js").dispatch();
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("public, no-cache"));
+    assert_eq!(client.get("/static/../Cargo.toml").dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn moderation_panel() {
+    let client = Client::new(rocket()).unwrap();
+    let post_form = |path: &str, body: &str| {
+        let body = format!("{}&{}", csrf_field(&client, "/login"), body);
+        client.post(path.to_string()).header(ContentType::Form).body(body).dispatch()
+    };
+    let spam = format!("spam {}", super::PasteID::new(8));
+    let response = client.post("/api/paste").body(&spam).dispatch();
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    assert_eq!(client.get("/admin").dispatch().headers().get_one("Location"), Some("/login"));
+
+    // Only accounts marked with `make-admin` get in.
+    let name = format!("mod-{}", super::PasteID::new(8).to_string().to_lowercase());
+    super::account::register(&name, "correct-horse").unwrap().unwrap();
+    post_form("/login", &format!("name={}&password=correct-horse", name));
+    assert_eq!(client.get("/admin").dispatch().status(), Status::Forbidden);
+    super::account::set_admin(&name, true).unwrap();
+    let page = client.get("/admin").dispatch().into_string().unwrap();
+    assert!(page.contains(&format!("/admin/{}", id)));
+
+    post_form(&format!("/admin/{}/quarantine", id), "");
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::NotFound);
+    post_form(&format!("/admin/{}/release", id), "");
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::Ok);
+    assert_eq!(super::meta::PasteMeta::load(&id).views, 1);
+
+    // Banned content can't be pasted again.
+    let preview = client.get(format!("/admin/{}", id)).dispatch().into_string().unwrap();
+    assert!(preview.contains(&spam));
+    let hash = super::moderation::content_hash(std::path::Path::new(&format!("upload/{}", id))).unwrap();
+    post_form("/admin/bans", &format!("kind=content&value={}", hash));
+    assert_eq!(client.post("/api/paste").body(&spam).dispatch().status(), Status::Forbidden);
+
+    // Nor edited or appended into a paste.
+    let owned = |body: &str| {
+        let response = client.post("/api/paste").body(body).dispatch();
+        let token = Header::new("X-Owner-Token", response.headers().get_one("X-Owner-Token").unwrap().to_string());
+        (extract_id(&response.into_string().unwrap()).unwrap(), token)
+    };
+    let (draft, token) = owned("draft");
+    let response = client.put(format!("/api/{}", draft)).header(token).body(&spam).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(download_paste(&client, &format!("api/{}", draft)), "draft");
+    let (log, token) = owned("spam ");
+    let response = client.post(format!("/api/{}/append", log)).header(token).body(&spam[5..]).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(client.get(format!("/api/{}", log)).dispatch().status(), Status::NotFound);
+
+    post_form(&format!("/admin/{}/delete", id), "");
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::NotFound);
+    let page = client.get("/admin").dispatch().into_string().unwrap();
+    assert!(page.contains(&format!("quarantine {}", id)) && page.contains(&format!("delete {}", id)));
+}
+
+#[test]
+fn abuse_reports() {
+    let client = Client::new(rocket()).unwrap();
+    let response = client.post("/api/paste").body("log in to your bank here").dispatch();
+    let id = extract_id(&response.into_string().unwrap()).unwrap();
+    let page = client.get(format!("/{}", id)).dispatch().into_string().unwrap();
+    assert!(page.contains(&format!("/api/{}/report", id)));
+
+    let report_from = |reporter: std::net::SocketAddr, body: &str| {
+        client.post(format!("/api/{}/report", id))
+            .header(ContentType::JSON)
+            .remote(reporter)
+            .body(body.to_string())
+            .dispatch()
+            .status()
+    };
+    let report = |reporter: u8, body: &str| report_from(std::net::SocketAddr::from(([192, 0, 2, reporter], 4000)), body);
+    assert_eq!(report(1, r#"{"reason": "boring"}"#), Status::BadRequest);
+    assert_eq!(report(1, r#"{"reason": "phishing", "details": "fake login"}"#), Status::Accepted);
+    assert_eq!(report(1, r#"{"reason": "spam"}"#), Status::Conflict);
+
+    // A whole IPv6 /64 counts as one address.
+    let ipv6 = |host: u16| std::net::SocketAddr::from(([0x2001, 0xdb8, 0, 1, 0, 0, 0, host], 4000));
+    assert_eq!(report_from(ipv6(1), r#"{"reason": "phishing"}"#), Status::Accepted);
+    assert_eq!(report_from(ipv6(2), r#"{"reason": "phishing"}"#), Status::Conflict);
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::Ok);
+
+    // The third address hides the paste until an admin looks at it.
+    assert_eq!(report(3, r#"{"reason": "phishing"}"#), Status::Accepted);
+    assert_eq!(client.get(format!("/api/{}", id)).dispatch().status(), Status::NotFound);
+    let reports = super::report::load(&id);
+    assert_eq!(reports.len(), 3);
+    assert!(reports.iter().all(|report| report.ip_hash.len() == 16 && !report.ip_hash.contains("192.0.2")));
+    assert!(super::report::queue().iter().any(|paste| paste.id == id && paste.meta.quarantined));
+}
@@ -0,0 +1,363 @@
+//! Resumable uploads following the tus 1.0.0 protocol (https://tus.io).
+//!
+//! Supported extensions are `creation` and `expiration`. Partial uploads live
+//! in `upload/.tus/<uid>` next to a small `<uid>.info` file; once the last
+//! byte arrives the data is moved to `upload/<id>` and becomes a normal paste,
+//! whose owner token comes back in `X-Owner-Token` on that last request.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use rocket::data::Data;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Debug, Responder, Response};
+use rocket::{Outcome, Route, State};
+
+use crate::api_key::Uploader;
+use crate::meta::{self, unix_now, PasteMeta};
+use crate::moderation;
+use crate::paste_id::PasteID;
+use crate::rate_limit::UploadLimit;
+use crate::{HOST, ID_LENGTH};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration";
+const TUS_DIR: &str = "upload/.tus";
+const UPLOAD_ID_LENGTH: usize = 32;
+
+/// Limits for resumable uploads, read from `tus_max_size` (bytes) and
+/// `tus_expiry` (seconds) in `Rocket.toml`.
+pub struct TusConfig {
+    max_size: u64,
+    expiry: Duration,
+}
+
+/// Uploads with a `PATCH` in progress. Another one for the same upload gets
+/// a 409 instead of appending in between.
+#[derive(Default)]
+pub struct Patching(Mutex<HashSet<String>>);
+
+/// Held while a `PATCH` writes to an upload.
+struct PatchLock<'a> {
+    patching: &'a Patching,
+    uid: String,
+}
+
+impl Patching {
+    fn lock(&self, uid: &PasteID<'_>) -> Option<PatchLock<'_>> {
+        let uid = uid.to_string();
+        match self.0.lock().unwrap().insert(uid.clone()) {
+            true => Some(PatchLock { patching: self, uid }),
+            false => None,
+        }
+    }
+}
+
+impl Drop for PatchLock<'_> {
+    fn drop(&mut self) {
+        self.patching.0.lock().unwrap().remove(&self.uid);
+    }
+}
+
+/// Headers shared by every tus request except `OPTIONS`.
+pub struct TusRequest {
+    upload_length: Option<u64>,
+    upload_offset: Option<u64>,
+}
+
+fn numeric_header(request: &Request<'_>, name: &str) -> Result<Option<u64>, ()> {
+    match request.headers().get_one(name) {
+        Some(value) => value.trim().parse().map(Some).map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for TusRequest {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        if request.headers().get_one("Tus-Resumable") != Some(TUS_VERSION) {
+            return Outcome::Failure((Status::PreconditionFailed, ()));
+        }
+        let upload_length = numeric_header(request, "Upload-Length");
+        let upload_offset = numeric_header(request, "Upload-Offset");
+        match (upload_length, upload_offset) {
+            (Ok(upload_length), Ok(upload_offset)) => {
+                Outcome::Success(TusRequest { upload_length, upload_offset })
+            }
+            _ => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
+
+/// An empty response carrying the `Tus-Resumable` header plus `headers`.
+pub struct TusResponse {
+    status: Status,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl TusResponse {
+    fn new(status: Status) -> TusResponse {
+        TusResponse { status, headers: Vec::new() }
+    }
+
+    fn header(mut self, name: &'static str, value: impl ToString) -> TusResponse {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+}
+
+impl<'r> Responder<'r> for TusResponse {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let mut response = Response::build();
+        response.status(self.status)
+            .raw_header("Tus-Resumable", TUS_VERSION)
+            .raw_header("Cache-Control", "no-store");
+        for (name, value) in self.headers {
+            response.raw_header(name, value);
+        }
+        response.ok()
+    }
+}
+
+/// Bookkeeping for one upload, stored as `key=value` lines.
+struct UploadInfo {
+    length: u64,
+    expires: u64,
+    paste: Option<String>,
+    /// The API key the upload was started with, recorded on the paste.
+    api_key: Option<String>,
+    /// Likewise the uploader's address hash.
+    ip_hash: Option<String>,
+}
+
+impl UploadInfo {
+    fn path(uid: &PasteID<'_>) -> String {
+        format!("{dir}/{uid}.info", dir = TUS_DIR, uid = uid)
+    }
+
+    fn load(uid: &PasteID<'_>) -> Option<UploadInfo> {
+        Self::parse(&fs::read_to_string(Self::path(uid)).ok()?)
+    }
+
+    fn parse(text: &str) -> Option<UploadInfo> {
+        let (mut length, mut expires, mut paste, mut api_key, mut ip_hash) = (None, None, None, None, None);
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("length", value)) => length = value.parse().ok(),
+                Some(("expires", value)) => expires = value.parse().ok(),
+                Some(("paste", value)) => paste = Some(value.to_string()),
+                Some(("api_key", value)) => api_key = Some(value.to_string()),
+                Some(("ip_hash", value)) => ip_hash = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        Some(UploadInfo { length: length?, expires: expires?, paste, api_key, ip_hash })
+    }
+
+    fn save(&self, uid: &PasteID<'_>) -> io::Result<()> {
+        let mut text = format!("length={}\nexpires={}\n", self.length, self.expires);
+        if let Some(paste) = &self.paste {
+            text.push_str(&format!("paste={}\n", paste));
+        }
+        if let Some(key) = &self.api_key {
+            text.push_str(&format!("api_key={}\n", key));
+        }
+        if let Some(hash) = &self.ip_hash {
+            text.push_str(&format!("ip_hash={}\n", hash));
+        }
+        fs::write(Self::path(uid), text)
+    }
+
+    fn expired(&self) -> bool {
+        self.expires <= unix_now()
+    }
+}
+
+fn http_date(unix: u64) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(unix))
+}
+
+fn data_path(uid: &PasteID<'_>) -> String {
+    format!("{dir}/{uid}", dir = TUS_DIR, uid = uid)
+}
+
+/// Removes every upload whose expiry has passed, finished or not.
+fn sweep_expired() {
+    let entries = match fs::read_dir(TUS_DIR) {
+        Ok(entries) => entries,
+        Err(..) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.extension().map_or(true, |ext| ext != "info") {
+            continue;
+        }
+        let expired = fs::read_to_string(&path).ok()
+            .and_then(|text| UploadInfo::parse(&text))
+            .map_or(true, |info| info.expired());
+        if expired {
+            let _ = fs::remove_file(path.with_extension(""));
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+#[options("/api/tus")]
+fn tus_options(config: State<TusConfig>) -> TusResponse {
+    TusResponse::new(Status::NoContent)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", config.max_size)
+}
+
+#[post("/api/tus")]
+fn tus_create(_limit: UploadLimit, uploader: Uploader, tus: TusRequest, config: State<TusConfig>) -> Result<TusResponse, Debug<io::Error>> {
+    sweep_expired();
+    let length = match tus.upload_length {
+        Some(length) => length,
+        None => return Ok(TusResponse::new(Status::BadRequest)),
+    };
+    if length > config.max_size {
+        return Ok(TusResponse::new(Status::PayloadTooLarge)
+            .header("Tus-Max-Size", config.max_size));
+    }
+
+    let uid = PasteID::new(UPLOAD_ID_LENGTH);
+    let expires = unix_now() + config.expiry.as_secs();
+    let api_key = uploader.key.map(|key| key.id);
+    let info = UploadInfo { length, expires, paste: None, api_key, ip_hash: uploader.ip_hash };
+    File::create(data_path(&uid))?;
+    info.save(&uid)?;
+
+    let mut response = TusResponse::new(Status::Created)
+        .header("Location", format!("/api/tus/{uid}", uid = uid))
+        .header("Upload-Expires", http_date(expires));
+    if length == 0 {
+        let (id, token) = finish(&uid, info)?;
+        response = response.header("X-Paste-Url", paste_url(&id)).header("X-Owner-Token", token);
+    }
+    Ok(response)
+}
+
+#[head("/api/tus/<uid>")]
+fn tus_head(uid: PasteID<'_>, _tus: TusRequest) -> Option<TusResponse> {
+    let info = UploadInfo::load(&uid).filter(|info| !info.expired())?;
+    let offset = match &info.paste {
+        Some(..) => info.length,
+        None => fs::metadata(data_path(&uid)).ok()?.len(),
+    };
+    let mut response = TusResponse::new(Status::Ok)
+        .header("Upload-Offset", offset)
+        .header("Upload-Length", info.length)
+        .header("Upload-Expires", http_date(info.expires));
+    if let Some(paste) = info.paste {
+        response = response.header("X-Paste-Url", paste_url(&paste));
+    }
+    Some(response)
+}
+
+#[patch("/api/tus/<uid>", data = "<chunk>")]
+fn tus_patch(
+    uid: PasteID<'_>,
+    tus: TusRequest,
+    content_type: Option<&ContentType>,
+    patching: State<Patching>,
+    chunk: Data,
+) -> Result<Option<TusResponse>, Debug<io::Error>> {
+    let is_offset_stream = content_type
+        .map_or(false, |ct| ct.top() == "application" && ct.sub() == "offset+octet-stream");
+    if !is_offset_stream {
+        return Ok(Some(TusResponse::new(Status::UnsupportedMediaType)));
+    }
+    // Held until the response is built, so the offset checked below is
+    // still the one the chunk is appended at.
+    let _lock = match patching.lock(&uid) {
+        Some(lock) => lock,
+        None => return Ok(Some(TusResponse::new(Status::Conflict))),
+    };
+    let info = match UploadInfo::load(&uid).filter(|info| !info.expired()) {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+    if info.paste.is_some() {
+        return Ok(Some(TusResponse::new(Status::Gone)));
+    }
+    let path = data_path(&uid);
+    let current = fs::metadata(&path)?.len();
+    match tus.upload_offset {
+        Some(offset) if offset == current => {}
+        Some(..) => return Ok(Some(TusResponse::new(Status::Conflict))),
+        None => return Ok(Some(TusResponse::new(Status::BadRequest))),
+    }
+
+    // Whatever made it to disk before a dropped connection is kept, so the
+    // client can query the offset with HEAD and resume from there.
+    let mut file = OpenOptions::new().append(true).open(&path)?;
+    io::copy(&mut chunk.open().take(info.length - current), &mut file)?;
+    let offset = fs::metadata(&path)?.len();
+    if offset > info.length {
+        return Err(Debug(io::Error::new(io::ErrorKind::Other, "upload grew past its length")));
+    }
+    let expires = info.expires;
+    let mut response = TusResponse::new(Status::NoContent)
+        .header("Upload-Offset", offset)
+        .header("Upload-Expires", http_date(expires));
+    if offset == info.length {
+        let (id, token) = finish(&uid, info)?;
+        response = response.header("X-Paste-Url", paste_url(&id)).header("X-Owner-Token", token);
+    }
+    Ok(Some(response))
+}
+
+/// Moves a complete upload into `upload/<id>` and records the paste ID so
+/// that later `HEAD` requests can still report where it went. Like any new
+/// paste it is checked and given its meta before it can be read. Returns the
+/// paste ID and owner token.
+fn finish(uid: &PasteID<'_>, mut info: UploadInfo) -> io::Result<(String, String)> {
+    // The upload replaces the empty file that claims the ID.
+    let id = meta::claim_id(ID_LENGTH)?.0.to_string();
+    let partial = meta::partial_path(&id);
+    fs::rename(data_path(uid), Path::new(&partial))?;
+    moderation::check_content(Path::new(&partial))?;
+    let token = meta::new_token();
+    let meta = PasteMeta {
+        owner_token: Some(token.clone()),
+        api_key: info.api_key.clone(),
+        ip_hash: info.ip_hash.clone(),
+        created: Some(unix_now()),
+        ..PasteMeta::default()
+    };
+    meta.save(&id)?;
+    meta::publish(&id)?;
+    info.paste = Some(id.clone());
+    info.save(uid)?;
+    Ok((id, token))
+}
+
+fn paste_url(id: &str) -> String {
+    format!("{host}/api/{id}", host = HOST, id = id)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![tus_options, tus_create, tus_head, tus_patch]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("tus uploads", |rocket| {
+        let max_size = rocket.config().get_int("tus_max_size").unwrap_or(1 << 30);
+        let expiry = rocket.config().get_int("tus_expiry").unwrap_or(24 * 60 * 60);
+        if fs::create_dir_all(TUS_DIR).is_err() {
+            return Err(rocket);
+        }
+        Ok(rocket.manage(Patching::default()).manage(TusConfig {
+            max_size: max_size.max(0) as u64,
+            expiry: Duration::from_secs(expiry.max(0) as u64),
+        }))
+    })
+}
@@ -0,0 +1,281 @@
+//! WebSocket clipboard sync for the chatbox. Rocket can't upgrade
+//! connections, so this is a separate listener on `ws_port` (0 disables it).
+//! Browsers on an HTTPS page are pointed at `wss://<host>/ws`, which the
+//! proxy in front has to hand to the listener.
+//!
+//! Messages are JSON text frames. A client first joins a channel,
+//!
+//!     {"type": "join", "channel": "<name>", "token": "<token>"}
+//!
+//! and receives `{"type": "history", "entries": [...]}`, newest first. After
+//! that `{"type": "push", "text": "..."}` stores an entry, and every entry
+//! stored in the channel, by any device or `POST /c/<name>`, arrives as
+//! `{"type": "entry", "time": ..., "text": "..."}`.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, Route, State};
+use rocket_contrib::json::Json;
+use serde::Deserialize;
+use serde_json::json;
+use tungstenite::handshake::server::{ErrorResponse, Request as Handshake, Response as Accepted};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+
+use crate::channel::{valid_name, Channel, Channels};
+use crate::rate_limit::{self, forwarded_for};
+
+const MAX_CONNECTIONS: usize = 256;
+const MAX_CONNECTIONS_PER_ADDRESS: usize = 8;
+/// How long a read waits before checking for entries to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `ws_port` and the optional public `ws_url` (e.g. `wss://copy.red/ws`
+/// behind a proxy) from `Rocket.toml`, and the `trusted_proxies` whose
+/// `X-Forwarded-For` and `X-Forwarded-Proto` are believed.
+pub struct WsConfig {
+    port: u16,
+    url: Option<String>,
+    tls: bool,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Join { channel: String, token: String },
+    Push { text: String },
+}
+
+/// The `Host` header, used to point browsers at the listener, and whether
+/// the page was served over HTTPS.
+pub struct Origin {
+    host: Option<String>,
+    https: bool,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Origin {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<WsConfig>>().succeeded();
+        let proxied = match (request.remote(), &config) {
+            (Some(peer), Some(config)) => config.trusted_proxies.contains(&peer.ip()),
+            _ => false,
+        };
+        let forwarded_https = proxied && request.headers().get_one("X-Forwarded-Proto") == Some("https");
+        Outcome::Success(Origin {
+            host: request.headers().get_one("Host").map(String::from),
+            https: forwarded_https || config.map_or(false, |config| config.tls),
+        })
+    }
+}
+
+/// Where the chatbox should connect; `null` when the listener is disabled.
+/// HTTPS pages can't open `ws:` connections, so they get the same origin's
+/// `/ws`.
+#[get("/api/ws")]
+fn endpoint(config: State<WsConfig>, origin: Origin) -> Json<serde_json::Value> {
+    let url = match (&config.url, config.port, origin.host) {
+        (Some(url), _, _) => Some(url.clone()),
+        (None, 0, _) | (None, _, None) => None,
+        (None, _, Some(host)) if origin.https => Some(format!("wss://{}/ws", host)),
+        (None, port, Some(host)) => {
+            let hostname = host.rsplitn(2, ':').last().unwrap_or(&host).to_string();
+            Some(format!("ws://{}:{}", hostname, port))
+        }
+    };
+    Json(json!({ "url": url }))
+}
+
+/// Open connections per client address.
+type OpenConnections = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// One of a client's `MAX_CONNECTIONS_PER_ADDRESS`, given back when dropped.
+struct ConnectionSlot {
+    open: OpenConnections,
+    client: IpAddr,
+}
+
+impl ConnectionSlot {
+    fn take(open: &OpenConnections, client: IpAddr) -> Option<ConnectionSlot> {
+        let mut connections = open.lock().unwrap();
+        let count = connections.entry(client).or_insert(0);
+        if *count >= MAX_CONNECTIONS_PER_ADDRESS {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionSlot { open: open.clone(), client })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut connections = self.open.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.client);
+            }
+        }
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: serde_json::Value) -> tungstenite::Result<()> {
+    socket.write_message(Message::Text(message.to_string()))
+}
+
+fn is_timeout(error: &tungstenite::Error) -> bool {
+    match error {
+        tungstenite::Error::Io(e) => e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut,
+        _ => false,
+    }
+}
+
+/// Waits for the join message and checks the channel token.
+fn join(socket: &mut WebSocket<TcpStream>) -> tungstenite::Result<Option<String>> {
+    let message = match socket.read_message()? {
+        Message::Text(text) => serde_json::from_str::<ClientMessage>(&text).ok(),
+        _ => None,
+    };
+    let (name, token) = match message {
+        Some(ClientMessage::Join { channel, token }) if valid_name(&channel) => (channel, token),
+        _ => {
+            send(socket, json!({ "type": "error", "message": "expected a join message" }))?;
+            return Ok(None);
+        }
+    };
+    let channel = Channel::open(&name);
+    if channel.authorize(&token).is_err() {
+        send(socket, json!({ "type": "error", "message": "unknown channel or wrong token" }))?;
+        return Ok(None);
+    }
+    let history = channel.history().unwrap_or_default();
+    send(socket, json!({ "type": "history", "entries": history }))?;
+    Ok(Some(name))
+}
+
+fn serve(stream: TcpStream, channels: &Channels, trusted: &[IpAddr], open: &OpenConnections) -> tungstenite::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let peer = stream.peer_addr()?.ip();
+    let config = WebSocketConfig {
+        max_message_size: Some(channels.max_size as usize + 1024),
+        ..WebSocketConfig::default()
+    };
+    // Behind a proxy the client is only known once its headers are read.
+    let mut slot = None;
+    let callback = |request: &Handshake, response: Accepted| -> Result<Accepted, ErrorResponse> {
+        let client = match trusted.contains(&peer) {
+            true => {
+                let headers = request.headers().get_all("X-Forwarded-For").iter().filter_map(|value| value.to_str().ok());
+                forwarded_for(headers, trusted).unwrap_or(peer)
+            }
+            false => peer,
+        };
+        slot = ConnectionSlot::take(open, client);
+        match slot {
+            Some(..) => Ok(response),
+            None => {
+                let mut refused = ErrorResponse::new(Some("too many connections\n".to_string()));
+                *refused.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                Err(refused)
+            }
+        }
+    };
+    let mut socket = tungstenite::server::accept_hdr_with_config(stream, callback, Some(config))
+        .map_err(|_| tungstenite::Error::ConnectionClosed)?;
+
+    let name = match join(&mut socket)? {
+        Some(name) => name,
+        None => return socket.close(None),
+    };
+    let entries = channels.subscribe(&name);
+    socket.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    loop {
+        match socket.read_message() {
+            Ok(Message::Text(text)) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(ClientMessage::Push { text }) => channels.store(&name, text.as_bytes())?,
+                _ => send(&mut socket, json!({ "type": "error", "message": "expected a push message" }))?,
+            },
+            Ok(Message::Close(..)) => return Ok(()),
+            Ok(..) => {}
+            Err(ref e) if is_timeout(e) => {}
+            Err(e) => return Err(e),
+        }
+        while let Ok(entry) = entries.try_recv() {
+            let mut message = serde_json::to_value(&entry).unwrap_or_default();
+            message["type"] = json!("entry");
+            send(&mut socket, message)?;
+        }
+    }
+}
+
+fn listen(listener: TcpListener, channels: Arc<Channels>, trusted: Arc<Vec<IpAddr>>) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let open = OpenConnections::default();
+    for stream in listener.incoming().filter_map(Result::ok) {
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let channels = channels.clone();
+        let connections = connections.clone();
+        let trusted = trusted.clone();
+        let open = open.clone();
+        thread::spawn(move || {
+            let _ = serve(stream, &channels, &trusted, &open);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![endpoint]
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("websocket config", |rocket| {
+        let config = rocket.config();
+        let port = match u16::try_from(config.get_int("ws_port").unwrap_or(0)) {
+            Ok(port) => port,
+            Err(..) => {
+                eprintln!("ws_port must be between 0 and 65535");
+                return Err(rocket);
+            }
+        };
+        let url = config.get_str("ws_url").ok().map(String::from);
+        let tls = config.tls_enabled();
+        let trusted_proxies = rate_limit::trusted_proxies(config);
+        Ok(rocket.manage(WsConfig { port, url, tls, trusted_proxies }))
+    })
+}
+
+/// Starts the listener next to rocket's, on the same address.
+pub fn listener() -> impl Fairing {
+    AdHoc::on_launch("websocket listener", |rocket| {
+        let (port, trusted) = match rocket.state::<WsConfig>() {
+            Some(config) => (config.port, Arc::new(config.trusted_proxies.clone())),
+            None => return,
+        };
+        let channels = match rocket.state::<Arc<Channels>>() {
+            Some(channels) if port != 0 => channels.clone(),
+            _ => return,
+        };
+        match TcpListener::bind((rocket.config().address.as_str(), port)) {
+            Ok(listener) => {
+                thread::spawn(move || listen(listener, channels, trusted));
+            }
+            Err(e) => eprintln!("websocket listener on port {} failed: {}", port, e),
+        }
+    })
+}
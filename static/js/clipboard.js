@@ -0,0 +1,122 @@
+// Clipboard widgets of the paste pages, kept out of the HTML so that the
+// Content-Security-Policy needs no inline code for them: the copy button of
+// a new paste, device pairing (`pairing.rs`) and the clipboard history of
+// the paired channel, synced over the WebSocket listener (`ws.rs`). The
+// pairing result is remembered in localStorage as `channel`/`channelToken`.
+(function () {
+  'use strict';
+
+  function copy(text) {
+    if (navigator.clipboard) { navigator.clipboard.writeText(text); }
+  }
+
+  var copyButton = document.getElementById('copy2boardButton');
+  if (copyButton) {
+    copyButton.addEventListener('click', function () {
+      var icon = document.getElementById('copy2boardIcon');
+      copy(document.getElementById('copy2board').textContent.trim());
+      icon.classList.add('animate-bounce');
+      setTimeout(function () { icon.classList.remove('animate-bounce'); }, 300);
+    });
+  }
+
+  function setupPairing(root) {
+    var code = document.getElementById('pair-code');
+    var done = document.getElementById('pair-done');
+    var error = document.getElementById('pair-error');
+
+    function showChannel() {
+      var channel = localStorage.getItem('channel');
+      done.hidden = !channel;
+      document.getElementById('pair-channel').textContent = channel ? '/c/' + channel : '';
+    }
+    function save(paired) {
+      localStorage.setItem('channel', paired.channel);
+      localStorage.setItem('channelToken', paired.token);
+      window.dispatchEvent(new CustomEvent('paired'));
+      error.hidden = true;
+      showChannel();
+    }
+
+    document.getElementById('pair-request').addEventListener('click', function () {
+      fetch('/api/pair', { method: 'POST' })
+        .then(function (r) { return r.json(); })
+        .then(function (paired) { code.textContent = paired.code; save(paired); });
+    });
+    document.getElementById('pair-form').addEventListener('submit', function (event) {
+      event.preventDefault();
+      var entered = document.getElementById('pair-entered').value;
+      fetch('/api/pair/' + encodeURIComponent(entered), { method: 'POST' })
+        .then(function (r) { if (!r.ok) { throw r.status; } return r.json(); })
+        .then(save)
+        .catch(function (status) {
+          error.textContent = status === 429 ? root.dataset.errorLimit : root.dataset.errorUnknown;
+          error.hidden = false;
+        });
+    });
+    showChannel();
+  }
+
+  function setupChatbox(root) {
+    var list = document.getElementById('chatbox-entries');
+    var draft = document.getElementById('chatbox-draft');
+    var socket = null, rejected = false;
+
+    function add(text) {
+      var entry = document.createElement('div');
+      entry.className = 'bg-gray-300 w-3/4 mx-4 my-2 p-2 rounded-lg whitespace-pre-wrap break-words cursor-pointer';
+      entry.title = root.dataset.copyTitle;
+      entry.textContent = text;
+      entry.addEventListener('click', function () { copy(text); });
+      list.appendChild(entry);
+    }
+    function connect() {
+      var channel = localStorage.getItem('channel') || '';
+      document.getElementById('chatbox-channel').textContent = '@' + channel;
+      document.getElementById('chatbox-no-channel').hidden = !!channel;
+      if (!channel || rejected) { return; }
+      fetch('/api/ws').then(function (r) { return r.json(); }).then(function (endpoint) {
+        if (!endpoint.url) { return; }
+        var ws = new WebSocket(endpoint.url);
+        ws.onopen = function () {
+          ws.send(JSON.stringify({ type: 'join', channel: channel, token: localStorage.getItem('channelToken') }));
+        };
+        ws.onmessage = function (e) {
+          var message = JSON.parse(e.data);
+          if (message.type === 'history') {
+            list.querySelectorAll('div').forEach(function (entry) { entry.remove(); });
+            message.entries.reverse().forEach(function (entry) { add(entry.text); });
+          } else if (message.type === 'entry') {
+            add(message.text);
+          } else if (message.type === 'error') {
+            rejected = true;
+          }
+          list.scrollTop = list.scrollHeight;
+        };
+        ws.onclose = function () {
+          if (socket === ws) { socket = null; }
+          setTimeout(connect, 3000);
+        };
+        socket = ws;
+      });
+    }
+
+    window.addEventListener('paired', function () {
+      if (socket) { socket.close(); }
+      rejected = false;
+      connect();
+    });
+    document.getElementById('chatbox-send').addEventListener('click', function () {
+      if (socket && draft.value) {
+        socket.send(JSON.stringify({ type: 'push', text: draft.value }));
+        draft.value = '';
+      }
+    });
+    connect();
+  }
+
+  var pairing = document.getElementById('pairing');
+  if (pairing) { setupPairing(pairing); }
+  var chatbox = document.getElementById('chatbox');
+  if (chatbox) { setupChatbox(chatbox); }
+})();